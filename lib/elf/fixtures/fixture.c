@@ -0,0 +1,11 @@
+/*
+ * Source of fixture.so, the test image of the crate, built on x86_64 with:
+ * gcc -Os -fPIC -shared -nostdlib -fno-asynchronous-unwind-tables \
+ *     -Wl,--build-id=sha1,-z,noseparate-code,-z,norelro,--hash-style=gnu -o fixture.so fixture.c
+ * strip --strip-debug -R .comment fixture.so
+ */
+
+static int counter = 1;
+int *counter_ptr = &counter;
+
+int get_counter(void) { return *counter_ptr; }
@@ -2,18 +2,34 @@
 
 extern crate alloc;
 
-use crate::program::Program;
-use alloc::vec::Vec;
+use crate::{
+    note::{NT_GNU_BUILD_ID, Note, Notes},
+    program::Program,
+    rela::Relas,
+    section::{Section, string_at},
+    symbol::{Symbol, Symbols},
+};
+use alloc::{string::ToString, vec::Vec};
 
+pub mod note;
 pub mod program;
+pub mod rela;
+pub mod section;
+pub mod symbol;
 
 pub use program::{Flags as PFlags, Type as PType};
+pub use section::Type as SType;
+pub use symbol::{Bind as SymBind, Type as SymType};
 
 const ELF_HEADER: &[u8] = &[0x7f, 0x45, 0x4c, 0x46];
 const ELF_CLASS_32: u8 = 1;
 const ELF_CLASS_64: u8 = 2;
 const PH_SIZE_32: usize = 32;
 const PH_SIZE_64: usize = 56;
+const SH_SIZE_32: usize = 40;
+const SH_SIZE_64: usize = 64;
+const EH_SIZE_32: usize = 52;
+const EH_SIZE_64: usize = 64;
 
 #[macro_export]
 macro_rules! int {
@@ -69,17 +85,34 @@ pub struct Elf {
     pub e_machine: ElfMachine,
     pub e_type: ElfType,
     pub e_entry: usize,
+    /** File offset of the program header table */
+    pub e_phoff: usize,
+    /** Size of a program header */
+    pub e_phentsize: usize,
     pub p_headers: Vec<Program>,
+    /** File offset of the section header table */
+    pub e_shoff: usize,
+    pub s_headers: Vec<Section>,
+    class: u8,
+    endian: u8,
 }
 
 impl Elf {
     pub fn parse(bytes: &[u8]) -> Result<Self, ElfError> {
-        if !bytes.starts_with(ELF_HEADER) {
+        if !bytes.starts_with(ELF_HEADER) || bytes.len() < EH_SIZE_32 {
             return Err(ElfError::InvalidHeader);
         }
 
         let class = bytes[4];
         let endian = bytes[5];
+        let eh_size = if class == ELF_CLASS_32 {
+            EH_SIZE_32
+        } else {
+            EH_SIZE_64
+        };
+        if bytes.len() < eh_size {
+            return Err(ElfError::InvalidHeader);
+        }
 
         let e_abi = match bytes[7] {
             0x00 => ElfAbi::SystemV,
@@ -121,23 +154,65 @@ impl Elf {
             int!(u64, bytes, 32, endian) as usize
         };
 
+        let e_phentsize = if class == ELF_CLASS_32 {
+            PH_SIZE_32
+        } else {
+            PH_SIZE_64
+        };
+
         let e_phnum = if class == ELF_CLASS_32 {
             int!(u16, bytes, 44, endian) as usize
         } else {
             int!(u16, bytes, 56, endian) as usize
         };
 
-        /* read section headers */
-        let mut sections = Vec::new();
-        for sec in 0..e_phnum {
-            let (sh_start, sh_end) = if class == ELF_CLASS_32 {
-                (sec * PH_SIZE_32, (sec + 1) * PH_SIZE_32)
-            } else {
-                (sec * PH_SIZE_64, (sec + 1) * PH_SIZE_64)
-            };
-            let section =
-                Program::parse(&bytes[e_phoff + sh_start..e_phoff + sh_end], class, endian);
-            sections.push(section);
+        /* read program headers, which are needed to load the image */
+        let programs = (0..e_phnum)
+            .map(|prog| {
+                let ph_start = e_phoff.checked_add(prog * e_phentsize)?;
+                let header = bytes.get(ph_start..ph_start.checked_add(e_phentsize)?)?;
+                Some(Program::parse(header, class, endian))
+            })
+            .collect::<Option<_>>()
+            .ok_or(ElfError::InvalidHeader)?;
+
+        let (e_shoff, e_shnum, e_shstrndx) = if class == ELF_CLASS_32 {
+            (
+                int!(u32, bytes, 32, endian) as usize,
+                int!(u16, bytes, 48, endian) as usize,
+                int!(u16, bytes, 50, endian) as usize,
+            )
+        } else {
+            (
+                int!(u64, bytes, 40, endian) as usize,
+                int!(u16, bytes, 60, endian) as usize,
+                int!(u16, bytes, 62, endian) as usize,
+            )
+        };
+        let sh_size = if class == ELF_CLASS_32 {
+            SH_SIZE_32
+        } else {
+            SH_SIZE_64
+        };
+
+        /*
+         * read section headers, then their names from the section name string table. They are not
+         * needed to load the image, so a table out of the file is left empty.
+         */
+        let mut sections: Vec<Section> = (0..e_shnum)
+            .map(|sec| {
+                let sh_start = e_shoff.checked_add(sec * sh_size)?;
+                let header = bytes.get(sh_start..sh_start.checked_add(sh_size)?)?;
+                Some(Section::parse(header, class, endian))
+            })
+            .collect::<Option<_>>()
+            .unwrap_or_default();
+        if let Some(shstrtab) = sections.get(e_shstrndx).and_then(|sec| sec.data(bytes)) {
+            for section in &mut sections {
+                section.name = string_at(shstrtab, section.sh_name)
+                    .unwrap_or("")
+                    .to_string();
+            }
         }
 
         Ok(Elf {
@@ -145,7 +220,221 @@ impl Elf {
             e_type,
             e_machine,
             e_entry,
-            p_headers: sections,
+            e_phoff,
+            e_phentsize,
+            p_headers: programs,
+            e_shoff,
+            s_headers: sections,
+            class,
+            endian,
         })
     }
+
+    /** Find a section by name. */
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.s_headers.iter().find(|section| section.name == name)
+    }
+
+    /**
+     * Iterate over a `SHT_SYMTAB` or `SHT_DYNSYM` table, names are read from its linked string
+     * table.
+     */
+    pub fn symbols<'a>(&self, bytes: &'a [u8], table: &Section) -> Symbols<'a> {
+        let strtab = self
+            .s_headers
+            .get(table.sh_link)
+            .and_then(|section| section.data(bytes))
+            .unwrap_or(&[]);
+        Symbols::new(
+            table.data(bytes).unwrap_or(&[]),
+            strtab,
+            self.class,
+            self.endian,
+        )
+    }
+
+    /** Symbols of `.symtab`, or of `.dynsym` if the image is stripped. */
+    pub fn symtab<'a>(&self, bytes: &'a [u8]) -> Option<Symbols<'a>> {
+        let table = self
+            .s_headers
+            .iter()
+            .find(|section| section.sh_type == SType::Symtab)
+            .or_else(|| {
+                self.s_headers
+                    .iter()
+                    .find(|section| section.sh_type == SType::Dynsym)
+            })?;
+        Some(self.symbols(bytes, table))
+    }
+
+    /** Find the function or object symbol containing `addr`. */
+    pub fn symbol_at<'a>(&self, bytes: &'a [u8], addr: usize) -> Option<Symbol<'a>> {
+        self.symtab(bytes)?.find(|symbol| {
+            matches!(symbol.st_type, SymType::Func | SymType::Object)
+                && symbol.st_shndx != 0
+                && symbol.contains(addr)
+        })
+    }
+
+    /** Iterate over the notes of `PT_NOTE` segments, or of `SHT_NOTE` sections without segments. */
+    pub fn notes<'a>(&'a self, bytes: &'a [u8]) -> impl Iterator<Item = Note<'a>> + 'a {
+        let segments = self
+            .p_headers
+            .iter()
+            .filter(|prog| matches!(prog.p_type, PType::Note))
+            .filter_map(|prog| {
+                let data = bytes.get(prog.p_offset..prog.p_offset.checked_add(prog.p_filesz)?)?;
+                Some(Notes::new(data, self.endian, prog.p_align))
+            });
+        let sections = self
+            .s_headers
+            .iter()
+            .filter(|section| self.p_headers.is_empty() && section.sh_type == SType::Note)
+            .filter_map(|section| {
+                Some(Notes::new(
+                    section.data(bytes)?,
+                    self.endian,
+                    section.sh_addralign,
+                ))
+            });
+        segments.chain(sections).flatten()
+    }
+
+    /** The GNU build-id, from the `NT_GNU_BUILD_ID` note. */
+    pub fn build_id<'a>(&'a self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        self.notes(bytes)
+            .find(|note| note.name == b"GNU" && note.n_type == NT_GNU_BUILD_ID)
+            .map(|note| note.desc)
+    }
+
+    /**
+     * Iterate over a relocation table with addends, as found in `SHT_RELA` sections or at
+     * `DT_RELA` of the dynamic section. An `entsize` of `0` is the size of an entry.
+     */
+    pub fn relas<'a>(&self, table: &'a [u8], entsize: usize) -> Relas<'a> {
+        Relas::new(table, self.class, self.endian, entsize)
+    }
+
+    /** Relocations of a `SHT_RELA` section such as `.rela.dyn`. */
+    pub fn section_relas<'a>(&self, bytes: &'a [u8], section: &Section) -> Relas<'a> {
+        self.relas(section.data(bytes).unwrap_or(&[]), section.sh_entsize)
+    }
+
+    /** Sections holding relocations with addends. */
+    pub fn rela_sections(&self) -> impl Iterator<Item = &Section> {
+        self.s_headers
+            .iter()
+            .filter(|section| section.sh_type == SType::Rela)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rela::Rela;
+
+    /** A shared object built from `fixtures/fixture.c` */
+    const FIXTURE: &[u8] = include_bytes!("../fixtures/fixture.so");
+    /** `R_X86_64_RELATIVE` */
+    const R_RELATIVE: u32 = 8;
+
+    #[test]
+    fn sections() {
+        let elf = Elf::parse(FIXTURE).unwrap();
+        assert_eq!(elf.s_headers.len(), 15);
+        assert_eq!(elf.s_headers[0].sh_type, SType::Null);
+
+        let text = elf.section(".text").unwrap();
+        assert_eq!(text.sh_type, SType::Progbits);
+        assert_eq!((text.sh_addr, text.sh_size), (0x240, 13));
+        assert_eq!(elf.section(".dynsym").unwrap().sh_type, SType::Dynsym);
+        assert!(elf.section(".bss").is_none());
+    }
+
+    #[test]
+    fn symbols() {
+        let elf = Elf::parse(FIXTURE).unwrap();
+        let names: Vec<&str> = elf.symtab(FIXTURE).unwrap().map(|sym| sym.name).collect();
+        assert_eq!(
+            names,
+            [
+                "",
+                "counter",
+                "_DYNAMIC",
+                "_GLOBAL_OFFSET_TABLE_",
+                "get_counter",
+                "counter_ptr"
+            ]
+        );
+
+        let function = elf.symbol_at(FIXTURE, 0x240 + 12).unwrap();
+        assert_eq!(function.name, "get_counter");
+        assert_eq!(
+            (function.st_type, function.st_bind),
+            (SymType::Func, SymBind::Global)
+        );
+        assert_eq!(elf.symbol_at(FIXTURE, 0x1350).unwrap().name, "counter");
+        assert!(elf.symbol_at(FIXTURE, 0x240 + 13).is_none());
+    }
+
+    #[test]
+    fn notes() {
+        let elf = Elf::parse(FIXTURE).unwrap();
+        assert_eq!(
+            elf.build_id(FIXTURE).unwrap(),
+            [
+                0x97, 0xc0, 0xc8, 0xff, 0x57, 0x26, 0x76, 0xa0, 0x91, 0xcb, 0x30, 0x3b, 0x4c, 0x12,
+                0xc3, 0x88, 0x73, 0x66, 0x06, 0x89
+            ]
+        );
+    }
+
+    #[test]
+    fn relocations() {
+        let elf = Elf::parse(FIXTURE).unwrap();
+        let sections: Vec<&str> = elf
+            .rela_sections()
+            .map(|section| section.name.as_str())
+            .collect();
+        assert_eq!(sections, [".rela.dyn"]);
+
+        let relas: Vec<Rela> = elf
+            .section_relas(FIXTURE, elf.section(".rela.dyn").unwrap())
+            .collect();
+        assert_eq!(relas.len(), 2);
+        assert_eq!(
+            (relas[0].r_offset, relas[0].r_type, relas[0].r_addend),
+            (0x1358, R_RELATIVE, 0x1350)
+        );
+        assert_eq!(relas[1].r_offset, 0x1330);
+        assert_eq!(relas[1].r_sym, 2);
+    }
+
+    #[test]
+    fn truncated_program_headers() {
+        let elf = Elf::parse(FIXTURE).unwrap();
+        let ph_end = elf.e_phoff + elf.p_headers.len() * elf.e_phentsize;
+        assert!(Elf::parse(&FIXTURE[..ph_end]).is_ok());
+        assert!(matches!(
+            Elf::parse(&FIXTURE[..ph_end - 1]),
+            Err(ElfError::InvalidHeader)
+        ));
+
+        let mut bytes = FIXTURE.to_vec();
+        bytes[32..40].copy_from_slice(&u64::MAX.to_le_bytes()); // e_phoff
+        assert!(matches!(Elf::parse(&bytes), Err(ElfError::InvalidHeader)));
+        assert!(matches!(
+            Elf::parse(&FIXTURE[..40]),
+            Err(ElfError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn truncated_section_headers() {
+        let e_shoff = Elf::parse(FIXTURE).unwrap().e_shoff;
+        let elf = Elf::parse(&FIXTURE[..e_shoff]).unwrap();
+        assert!(elf.s_headers.is_empty());
+        assert_eq!(elf.p_headers.len(), 5);
+        assert!(elf.symtab(FIXTURE).is_none());
+    }
 }
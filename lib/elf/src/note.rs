@@ -0,0 +1,64 @@
+use crate::int;
+
+/** Type of the GNU build-id note */
+pub const NT_GNU_BUILD_ID: u32 = 3;
+const NOTE_HEADER_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Note<'a> {
+    /** Owner of the note, without the null byte */
+    pub name: &'a [u8],
+    pub n_type: u32,
+    pub desc: &'a [u8],
+}
+
+/**
+ * Iterator over the notes of a `PT_NOTE` segment or `SHT_NOTE` section, names and descriptors are
+ * padded to `align`.
+ */
+pub struct Notes<'a> {
+    bytes: &'a [u8],
+    endian: u8,
+    align: usize,
+    offset: usize,
+}
+
+impl<'a> Notes<'a> {
+    pub fn new(bytes: &'a [u8], endian: u8, align: usize) -> Self {
+        Self {
+            bytes,
+            endian,
+            /* notes are 4-byte aligned unless 8 is required */
+            align: if align == 8 { 8 } else { 4 },
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Notes<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + NOTE_HEADER_SIZE)?;
+        let n_namesz = int!(u32, bytes, 0, self.endian) as usize;
+        let n_descsz = int!(u32, bytes, 4, self.endian) as usize;
+        let n_type = int!(u32, bytes, 8, self.endian);
+
+        let name_start = self.offset + NOTE_HEADER_SIZE;
+        let desc_start = name_start
+            .checked_add(n_namesz)?
+            .next_multiple_of(self.align);
+        let desc_end = desc_start.checked_add(n_descsz)?;
+        let name = self.bytes.get(name_start..name_start + n_namesz)?;
+        let desc = self.bytes.get(desc_start..desc_end)?;
+        self.offset = desc_end.next_multiple_of(self.align);
+
+        Some(Note {
+            name: name.strip_suffix(&[0]).unwrap_or(name),
+            n_type,
+            desc,
+        })
+    }
+}
@@ -12,6 +12,7 @@ pub enum Type {
     Dynamic,
     Interp,
     Note,
+    Phdr,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -30,6 +31,7 @@ pub struct Program {
     pub p_addr: usize,
     pub p_filesz: usize,
     pub p_memsz: usize,
+    pub p_align: usize,
 }
 
 impl Program {
@@ -40,6 +42,7 @@ impl Program {
             0x02 => Type::Dynamic,
             0x03 => Type::Interp,
             0x04 => Type::Note,
+            0x06 => Type::Phdr,
             _ => Type::Null,
         };
 
@@ -90,6 +93,12 @@ impl Program {
             int!(u64, bytes, 40, endian) as usize
         };
 
+        let p_align = if class == ELF_CLASS_32 {
+            int!(u32, bytes, 28, endian) as usize
+        } else {
+            int!(u64, bytes, 48, endian) as usize
+        };
+
         Self {
             p_type,
             p_flags,
@@ -98,6 +107,7 @@ impl Program {
             p_addr,
             p_filesz,
             p_memsz,
+            p_align,
         }
     }
 }
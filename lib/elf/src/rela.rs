@@ -0,0 +1,74 @@
+use crate::{ELF_CLASS_32, int};
+
+const RELA_SIZE_32: usize = 12;
+const RELA_SIZE_64: usize = 24;
+
+/** Relocation entry with an explicit addend */
+#[derive(Clone, Copy, Debug)]
+pub struct Rela {
+    pub r_offset: usize,
+    /** Index of the symbol in the associated symbol table */
+    pub r_sym: usize,
+    /** Machine specific relocation type */
+    pub r_type: u32,
+    pub r_addend: i64,
+}
+
+impl Rela {
+    pub fn parse(bytes: &[u8], class: u8, endian: u8) -> Self {
+        if class == ELF_CLASS_32 {
+            let r_info = int!(u32, bytes, 4, endian);
+            Self {
+                r_offset: int!(u32, bytes, 0, endian) as usize,
+                r_sym: (r_info >> 8) as usize,
+                r_type: r_info & 0xff,
+                r_addend: int!(i32, bytes, 8, endian) as i64,
+            }
+        } else {
+            let r_info = int!(u64, bytes, 8, endian);
+            Self {
+                r_offset: int!(u64, bytes, 0, endian) as usize,
+                r_sym: (r_info >> 32) as usize,
+                r_type: r_info as u32,
+                r_addend: int!(i64, bytes, 16, endian),
+            }
+        }
+    }
+}
+
+/** Iterator over a relocation table, entries are `entsize` bytes apart. */
+pub struct Relas<'a> {
+    table: &'a [u8],
+    class: u8,
+    endian: u8,
+    entsize: usize,
+    offset: usize,
+}
+
+impl<'a> Relas<'a> {
+    /** An `entsize` of `0` is the size of an entry of the class. */
+    pub fn new(table: &'a [u8], class: u8, endian: u8, entsize: usize) -> Self {
+        let size = if class == ELF_CLASS_32 {
+            RELA_SIZE_32
+        } else {
+            RELA_SIZE_64
+        };
+        Self {
+            table,
+            class,
+            endian,
+            entsize: core::cmp::max(entsize, size),
+            offset: 0,
+        }
+    }
+}
+
+impl Iterator for Relas<'_> {
+    type Item = Rela;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.table.get(self.offset..self.offset + self.entsize)?;
+        self.offset += self.entsize;
+        Some(Rela::parse(bytes, self.class, self.endian))
+    }
+}
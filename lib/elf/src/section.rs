@@ -0,0 +1,116 @@
+use crate::{ELF_CLASS_32, int};
+use alloc::string::String;
+
+#[derive(Debug, PartialEq)]
+pub enum Type {
+    Null,
+    Progbits,
+    Symtab,
+    Strtab,
+    Rela,
+    Hash,
+    Dynamic,
+    Note,
+    Nobits,
+    Rel,
+    Dynsym,
+    Other(u32),
+}
+
+#[derive(Debug)]
+pub struct Section {
+    /** Name from the section header string table */
+    pub name: String,
+    /** Offset of the name in the section header string table */
+    pub sh_name: usize,
+    pub sh_type: Type,
+    pub sh_flags: usize,
+    pub sh_addr: usize,
+    pub sh_offset: usize,
+    pub sh_size: usize,
+    /** Index of the associated section, the string table of a symbol table */
+    pub sh_link: usize,
+    pub sh_info: usize,
+    pub sh_addralign: usize,
+    /** Size of an entry for sections holding a table */
+    pub sh_entsize: usize,
+}
+
+impl Section {
+    /** Parse a section header, the name is resolved later from the string table. */
+    pub fn parse(bytes: &[u8], class: u8, endian: u8) -> Self {
+        let sh_type = match int!(u32, bytes, 4, endian) {
+            0x00 => Type::Null,
+            0x01 => Type::Progbits,
+            0x02 => Type::Symtab,
+            0x03 => Type::Strtab,
+            0x04 => Type::Rela,
+            0x05 => Type::Hash,
+            0x06 => Type::Dynamic,
+            0x07 => Type::Note,
+            0x08 => Type::Nobits,
+            0x09 => Type::Rel,
+            0x0b => Type::Dynsym,
+            sh_type => Type::Other(sh_type),
+        };
+
+        let [sh_flags, sh_addr, sh_offset, sh_size] = if class == ELF_CLASS_32 {
+            [8, 12, 16, 20].map(|offset| int!(u32, bytes, offset, endian) as usize)
+        } else {
+            [8, 16, 24, 32].map(|offset| int!(u64, bytes, offset, endian) as usize)
+        };
+
+        let (sh_link, sh_info) = if class == ELF_CLASS_32 {
+            (
+                int!(u32, bytes, 24, endian) as usize,
+                int!(u32, bytes, 28, endian) as usize,
+            )
+        } else {
+            (
+                int!(u32, bytes, 40, endian) as usize,
+                int!(u32, bytes, 44, endian) as usize,
+            )
+        };
+
+        let (sh_addralign, sh_entsize) = if class == ELF_CLASS_32 {
+            (
+                int!(u32, bytes, 32, endian) as usize,
+                int!(u32, bytes, 36, endian) as usize,
+            )
+        } else {
+            (
+                int!(u64, bytes, 48, endian) as usize,
+                int!(u64, bytes, 56, endian) as usize,
+            )
+        };
+
+        Self {
+            name: String::new(),
+            sh_name: int!(u32, bytes, 0, endian) as usize,
+            sh_type,
+            sh_flags,
+            sh_addr,
+            sh_offset,
+            sh_size,
+            sh_link,
+            sh_info,
+            sh_addralign,
+            sh_entsize,
+        }
+    }
+
+    /** Content of the section in the file, `None` if out of the file or without content. */
+    pub fn data<'a>(&self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        if self.sh_type == Type::Nobits {
+            return None;
+        }
+        bytes.get(self.sh_offset..self.sh_offset.checked_add(self.sh_size)?)
+    }
+}
+
+/** Read the null-terminated string at `offset` of a string table. */
+pub fn string_at(strtab: &[u8], offset: usize) -> Option<&str> {
+    let bytes = strtab.get(offset..)?;
+    let len = bytes.iter().position(|byte| *byte == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
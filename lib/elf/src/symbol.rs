@@ -0,0 +1,125 @@
+use crate::{ELF_CLASS_32, int, section::string_at};
+
+const SYM_SIZE_32: usize = 16;
+const SYM_SIZE_64: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    NoType,
+    Object,
+    Func,
+    Section,
+    File,
+    Tls,
+    Other(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bind {
+    Local,
+    Global,
+    Weak,
+    Other(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub st_type: Type,
+    pub st_bind: Bind,
+    /** Index of the section defining the symbol, `0` if undefined */
+    pub st_shndx: usize,
+    pub st_value: usize,
+    pub st_size: usize,
+}
+
+impl<'a> Symbol<'a> {
+    pub fn parse(bytes: &[u8], strtab: &'a [u8], class: u8, endian: u8) -> Self {
+        let st_name = int!(u32, bytes, 0, endian) as usize;
+        let (st_info, st_shndx, st_value, st_size) = if class == ELF_CLASS_32 {
+            (
+                bytes[12],
+                int!(u16, bytes, 14, endian) as usize,
+                int!(u32, bytes, 4, endian) as usize,
+                int!(u32, bytes, 8, endian) as usize,
+            )
+        } else {
+            (
+                bytes[4],
+                int!(u16, bytes, 6, endian) as usize,
+                int!(u64, bytes, 8, endian) as usize,
+                int!(u64, bytes, 16, endian) as usize,
+            )
+        };
+
+        let st_type = match st_info & 0xf {
+            0x00 => Type::NoType,
+            0x01 => Type::Object,
+            0x02 => Type::Func,
+            0x03 => Type::Section,
+            0x04 => Type::File,
+            0x06 => Type::Tls,
+            st_type => Type::Other(st_type),
+        };
+        let st_bind = match st_info >> 4 {
+            0x00 => Bind::Local,
+            0x01 => Bind::Global,
+            0x02 => Bind::Weak,
+            st_bind => Bind::Other(st_bind),
+        };
+
+        Self {
+            name: string_at(strtab, st_name).unwrap_or(""),
+            st_type,
+            st_bind,
+            st_shndx,
+            st_value,
+            st_size,
+        }
+    }
+
+    /** Check if `addr` is in the symbol, a symbol without size only contains its own address. */
+    pub fn contains(&self, addr: usize) -> bool {
+        if self.st_size == 0 {
+            addr == self.st_value
+        } else {
+            (self.st_value..self.st_value.saturating_add(self.st_size)).contains(&addr)
+        }
+    }
+}
+
+/** Iterator over the entries of a symbol table. */
+pub struct Symbols<'a> {
+    table: &'a [u8],
+    strtab: &'a [u8],
+    class: u8,
+    endian: u8,
+    offset: usize,
+}
+
+impl<'a> Symbols<'a> {
+    pub fn new(table: &'a [u8], strtab: &'a [u8], class: u8, endian: u8) -> Self {
+        Self {
+            table,
+            strtab,
+            class,
+            endian,
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Symbols<'a> {
+    type Item = Symbol<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = if self.class == ELF_CLASS_32 {
+            SYM_SIZE_32
+        } else {
+            SYM_SIZE_64
+        };
+        let bytes = self.table.get(self.offset..self.offset + size)?;
+        self.offset += size;
+        Some(Symbol::parse(bytes, self.strtab, self.class, self.endian))
+    }
+}
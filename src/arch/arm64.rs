@@ -1,6 +1,7 @@
 pub mod cpu;
 mod gic;
 pub mod page;
+pub mod psci;
 mod syscall;
 mod trap;
 
@@ -28,6 +29,24 @@ pub unsafe fn disable_interrupts() {
     unsafe { asm!("msr DAIFSet, #2") };
 }
 
+/**
+ * Initialize a secondary CPU entered with the boot page table, and switch to kernel's page
+ * table.
+ */
+pub unsafe fn secondary_cpu_init() {
+    unsafe {
+        cpu::cpu_init();
+        page::set_ttbrx((crate::page::KERNEL_PT.assume_init().0 as u64) << crate::page::PAGE_BITS);
+        page::refresh_tlb();
+        gic_cpu_init();
+    }
+}
+
+/** Make a supervisor call from EL1, which switches to another task. */
+pub fn yield_now() {
+    unsafe { asm!("svc #0") };
+}
+
 pub fn soc_init(dtb: &DeviceTree) {
     /* map gic registers */
     'root: for node in &dtb.root.child_nodes {
@@ -48,8 +67,15 @@ pub fn soc_init(dtb: &DeviceTree) {
 
     /* initalize gic */
     unsafe {
-        gic_enable_irq(27);
         gicd_mmio_write(GICD_CTLR, 1);
+        gic_cpu_init();
+    }
+}
+
+/** Initialize the banked registers of the GIC for this CPU. */
+unsafe fn gic_cpu_init() {
+    unsafe {
+        gic_enable_irq(27);
         gicc_mmio_write(GICC_CTLR, 1);
         gicc_mmio_write(GICC_PMR, 0xff);
     }
@@ -51,6 +51,13 @@ pub unsafe fn cpu_init() {
     }
 }
 
+/** Affinity fields of `MPIDR_EL1`, which identify this CPU to PSCI. */
+pub fn mpidr_affinity() -> usize {
+    let mpidr: u64;
+    unsafe { asm!("mrs {}, MPIDR_EL1", out(reg) mpidr) };
+    (mpidr & 0xff_00ff_ffff) as usize
+}
+
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct Context {
@@ -25,6 +25,9 @@ const AF: u64 = 1 << 10;
 const PXN: u64 = 1 << 53;
 const UXN: u64 = 1 << 54;
 
+/** Bits 12 to 47 of a descriptor, the upper attributes are above them */
+const OUTPUT_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+
 unsafe fn mmu_enable() {
     let mut sctlr: u64;
     unsafe {
@@ -90,7 +93,16 @@ impl Entry for TableDescriptor {
         Self(descriptor)
     }
     fn page_number(&self) -> PhysPage {
-        PhysicalPage(self.0 as usize >> PAGE_BITS)
+        PhysicalPage((self.0 & OUTPUT_ADDR_MASK) as usize >> PAGE_BITS)
+    }
+    fn has_acl(&self, acl: PageACL) -> bool {
+        match acl {
+            PageACL::Read => self.is_valid(),
+            PageACL::Write => self.0 & AP2_RO == 0,
+            PageACL::Execute if self.has_acl(PageACL::User) => self.0 & UXN == 0,
+            PageACL::Execute => self.0 & PXN == 0,
+            PageACL::User => self.0 & AP1 > 0,
+        }
     }
 }
 
@@ -0,0 +1,60 @@
+/*!
+ * Power State Coordination Interface, used to start secondary CPUs.
+ */
+
+use core::arch::asm;
+use dtb::DeviceTree;
+
+const PSCI_CPU_ON: u64 = 0xc400_0003;
+
+/** Instruction used to call the PSCI firmware */
+#[derive(Clone, Copy)]
+pub enum Conduit {
+    Hvc,
+    Smc,
+}
+
+impl Conduit {
+    /** Read the conduit from the `method` property of the `/psci` node. */
+    pub fn from_dtb(dtb: &DeviceTree) -> Option<Self> {
+        let psci = dtb
+            .root
+            .child_nodes
+            .iter()
+            .find(|node| node.name == "psci")?;
+        match psci.get_property("method")? {
+            method if method.starts_with(b"hvc") => Some(Self::Hvc),
+            method if method.starts_with(b"smc") => Some(Self::Smc),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Start the CPU with affinity `target` at `entry` in EL1 with the MMU off, its `x0` is
+ * `context_id`.
+ *
+ * Returns the PSCI return code, `0` on success.
+ */
+pub fn cpu_on(conduit: Conduit, target: u64, entry: u64, context_id: u64) -> i64 {
+    let ret: i64;
+    unsafe {
+        match conduit {
+            Conduit::Hvc => asm!(
+                "hvc #0",
+                inlateout("x0") PSCI_CPU_ON => ret,
+                inlateout("x1") target => _,
+                inlateout("x2") entry => _,
+                inlateout("x3") context_id => _,
+            ),
+            Conduit::Smc => asm!(
+                "smc #0",
+                inlateout("x0") PSCI_CPU_ON => ret,
+                inlateout("x1") target => _,
+                inlateout("x2") entry => _,
+                inlateout("x3") context_id => _,
+            ),
+        }
+    };
+    ret
+}
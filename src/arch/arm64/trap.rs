@@ -4,8 +4,10 @@ use super::{
 };
 use crate::{
     arch::arm64::gic::*,
+    backtrace,
     page::{KERNEL_PT, Paging},
-    task::{SCHEDULER, Scheduler},
+    signal::{SIGILL, SIGSEGV},
+    task::{SCHEDULER, Scheduler, Task},
 };
 use core::arch::{asm, global_asm};
 
@@ -21,6 +23,13 @@ const ESR_EC_TRAPPED_MSR: u64 = 0x18;
 const ESR_EC_INST_ABORT: u64 = 0x20;
 const ESR_EC_DATA_ABORT: u64 = 0x24;
 
+/** Exception level and stack pointer the exception is taken from */
+const SPSR_M_MASK: u64 = 0b1111;
+const SPSR_M_EL0T: u64 = 0b0000;
+
+/** Data abort caused by a write */
+const ESR_ISS_WNR: u64 = 1 << 6;
+
 /** switch to kernel page table */
 unsafe fn to_kernel_pt() {
     let tbbrx_el1 = unsafe { ((*(&raw mut KERNEL_PT)).assume_init().0 as u64) << 12 };
@@ -30,50 +39,103 @@ unsafe fn to_kernel_pt() {
     }
 }
 
+/**
+ * Move the context of the next task, which has been written to `ctx`, to the stack the task
+ * returns with, and return the address of the moved context.
+ *
+ * Kernel tasks return to their own stacks, user tasks return with the trap stack of this CPU so
+ * the next trap taken from EL0 starts below the per-CPU area.
+ */
+pub(super) unsafe fn trap_frame(next_task: &Task, ctx: *mut Context) -> *mut Context {
+    let frame = if next_task.is_kernel() {
+        unsafe { (*ctx).sp as usize - size_of::<Context>() }
+    } else {
+        unsafe { asm!("msr SP_EL0, {}", in(reg) (*ctx).sp) };
+        crate::trap::per_cpu_ptr() - size_of::<Context>()
+    } as *mut Context;
+    unsafe { frame.write(ctx.read()) };
+    frame
+}
+
 /**
  * Kill a task, it is called by trap, and it does:
- * * Remove the task from scheduler.
+ * * Terminate the task with the exit code in scheduler.
  * * Set up the next task's conext.
  * * Switch to the next task's page table.
+ *
+ * Returns the context to return with.
  */
-pub unsafe fn kill_task(scheduler: &mut Scheduler, ctx: *mut Context) {
+pub unsafe fn kill_task(
+    scheduler: &mut Scheduler,
+    ctx: *mut Context,
+    exit_code: isize,
+) -> *mut Context {
     let current_pid = scheduler.current_task().pid;
-    scheduler.kill(current_pid);
+    scheduler.exit(current_pid, exit_code);
 
-    let next_task = scheduler.current_task();
+    let next_task = scheduler.schedule();
     let next_ctx = next_task.context.clone();
     unsafe { ctx.write(next_ctx) };
-    if !next_task.is_kernel() {
-        unsafe { asm!("msr SP_EL0, {}", in(reg) (*ctx).sp) };
-    }
 
     unsafe {
         next_task.page.switch_to();
         refresh_tlb();
+        trap_frame(next_task, ctx)
     }
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn el1_sync_trap_handler(ctx: *mut Context) {
+pub unsafe extern "C" fn el1_sync_trap_handler(ctx: *mut Context) -> *mut Context {
     unsafe {
         let esr_el1: u64;
         asm!("mrs {}, ESR_EL1", out(reg) esr_el1);
 
         to_kernel_pt();
         let ec = (esr_el1 >> ESR_EC_OFFSET) & ESR_EC_MASK;
-        if ec == ESR_EC_SVC64 {
-            super::syscall::syscall(ctx);
-        } else if ec == ESR_EC_UNKOWN
-            || ec == ESR_EC_WFI
-            || ec == ESR_EC_TRAPPED_MSR
-            || ec == ESR_EC_INST_ABORT
-            || ec == ESR_EC_DATA_ABORT
-        {
+        if (*ctx).pstate & SPSR_M_MASK != SPSR_M_EL0T {
+            /* taken from EL1 */
+            if ec != ESR_EC_SVC64 {
+                backtrace::kernel_trap((*ctx).elr_el1 as usize, (*ctx).x[29] as usize);
+                panic!("Kernel exception, ESR_EL1 = {:#x}", esr_el1);
+            }
+            task_switch(ctx) // raised by `yield_now`
+        } else if ec == ESR_EC_SVC64 {
+            super::syscall::syscall(ctx)
+        } else if ec == ESR_EC_INST_ABORT || ec == ESR_EC_DATA_ABORT {
+            let far_el1: u64;
+            asm!("mrs {}, FAR_EL1", out(reg) far_el1);
+            let write = ec == ESR_EC_DATA_ABORT && esr_el1 & ESR_ISS_WNR != 0;
+
+            let mut scheduler_guard = SCHEDULER.lock();
+            let scheduler = scheduler_guard.assume_init_mut();
+            let current_task = scheduler.current_task_mut();
+            if current_task.handle_page_fault(far_el1 as usize, write) {
+                current_task.page.switch_to();
+                refresh_tlb();
+                ctx
+            } else {
+                if current_task.is_kernel() {
+                    backtrace::kernel_trap((*ctx).elr_el1 as usize, (*ctx).x[29] as usize);
+                    panic!("Kernel page fault at {:#x}", far_el1);
+                }
+                current_task.signals.force(SIGSEGV);
+                drop(scheduler_guard);
+                task_switch(ctx)
+            }
+        } else if ec == ESR_EC_UNKOWN || ec == ESR_EC_WFI || ec == ESR_EC_TRAPPED_MSR {
             let mut scheduler_guard = SCHEDULER.lock();
             let scheduler = scheduler_guard.assume_init_mut();
-            kill_task(scheduler, ctx);
+            if scheduler.current_task().is_kernel() {
+                backtrace::kernel_trap((*ctx).elr_el1 as usize, (*ctx).x[29] as usize);
+                panic!("Kernel illegal instruction");
+            }
+            scheduler.current_task_mut().signals.force(SIGILL);
+            drop(scheduler_guard);
+            task_switch(ctx)
+        } else {
+            ctx
         }
-    };
+    }
 }
 
 /**
@@ -90,34 +152,36 @@ fn kernel_pt_do(func: impl Fn()) {
 }
 
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn el1_irq_trap_handler(ctx: *mut Context) {
+pub unsafe extern "C" fn el1_irq_trap_handler(ctx: *mut Context) -> *mut Context {
     unsafe { to_kernel_pt() };
 
     let irq = unsafe { gicc_mmio_read(GICC_IAR) };
     if irq == INTID_VTIMER {
         crate::time::timer();
         set_timer();
-        task_switch(ctx);
+        let frame = task_switch(ctx);
         kernel_pt_do(|| unsafe {
             gicc_mmio_write(GICC_EOIR, irq);
         });
+        frame
+    } else {
+        ctx
     }
 }
 
-fn task_switch(ctx: *mut Context) {
+/** Save the context of the current task, and switch to the next task. */
+fn task_switch(ctx: *mut Context) -> *mut Context {
     let mut scheduler_guard = SCHEDULER.lock();
     let scheduler = unsafe { scheduler_guard.assume_init_mut() };
-    if !scheduler.current_task().is_kernel() {
+    /* the stack pointer of traps taken from EL1 is saved on entry */
+    if unsafe { (*ctx).pstate } & SPSR_M_MASK == SPSR_M_EL0T {
         unsafe { asm!("mrs {}, SP_EL0", out(reg)(*ctx).sp) };
     }
 
     let next_task = scheduler.switch_task(ctx);
-    if !next_task.is_kernel() {
-        unsafe { asm!("msr SP_EL0, {}", in(reg) (*ctx).sp) };
-    }
-
     unsafe {
         next_task.page.switch_to();
         refresh_tlb();
+        trap_frame(next_task, ctx)
     }
 }
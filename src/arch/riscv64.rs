@@ -1,5 +1,6 @@
 pub mod cpu;
 pub mod page;
+pub mod sbi;
 mod syscall;
 mod trap;
 
@@ -39,6 +40,24 @@ pub unsafe fn enable_timer() {
     }
 }
 
+/** Enable the supervisor software interrupt, which is raised by [yield_now]. */
+pub unsafe fn enable_soft_interrupt() {
+    unsafe { sie_w(sie_r() | (1 << 1)) }; // set SSIE flag for sie
+}
+
+/** Initialize a secondary hart, which runs in supervisor mode. */
+pub unsafe fn secondary_cpu_init() {
+    unsafe {
+        enable_timer();
+        enable_soft_interrupt();
+    }
+}
+
+/** Raise a supervisor software interrupt, which switches to another task. */
+pub fn yield_now() {
+    unsafe { asm!("csrs sip, 2") }; // set SSIP flag
+}
+
 pub fn set_timer(interval: u64) {
     unsafe { asm!("csrw stimecmp, {}", in(reg) get_sys_time() + interval) };
 }
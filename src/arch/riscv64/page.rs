@@ -62,6 +62,15 @@ impl Entry for PageTableEntry {
     fn page_number(&self) -> PhysPage {
         PhysicalPage(self.0 as usize >> 10)
     }
+    fn has_acl(&self, acl: PageACL) -> bool {
+        let flag = match acl {
+            PageACL::Read => PTE_R_FLAG,
+            PageACL::Write => PTE_W_FLAG,
+            PageACL::Execute => PTE_X_FLAG,
+            PageACL::User => PTE_U_FLAG,
+        };
+        self.0 & flag > 0
+    }
 }
 
 pub type PageMapper = Sv39Mapper<PageTableEntry>;
@@ -0,0 +1,30 @@
+/*!
+ * Calls into the SBI firmware.
+ */
+
+use core::arch::asm;
+
+/** Hart State Management extension */
+const SBI_EXT_HSM: u64 = 0x48534d;
+const SBI_HSM_HART_START: u64 = 0;
+
+/**
+ * Start a stopped hart at `start_addr` in supervisor mode with the MMU off, its `a0` is the hart
+ * ID and its `a1` is `opaque`.
+ *
+ * Returns the SBI error code, `0` on success.
+ */
+pub fn hart_start(hart_id: u64, start_addr: u64, opaque: u64) -> i64 {
+    let error: i64;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") hart_id => error,
+            inlateout("a1") start_addr => _,
+            in("a2") opaque,
+            in("a6") SBI_HSM_HART_START,
+            in("a7") SBI_EXT_HSM,
+        )
+    };
+    error
+}
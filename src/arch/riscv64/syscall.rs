@@ -15,36 +15,57 @@ unsafe fn syscall_fork(scheduler: &mut Scheduler, ctx: *mut Context) {
     }
 }
 
+unsafe fn syscall_exec(scheduler: &mut Scheduler, ctx: *mut Context) {
+    let (path_ptr, argv_ptr, envp_ptr) = unsafe { ((*ctx).x[9], (*ctx).x[10], (*ctx).x[11]) };
+    match crate::syscall::syscall_exec(scheduler, path_ptr, argv_ptr, envp_ptr) {
+        Ok(_) => unsafe { ctx.write(scheduler.current_task().context.clone()) },
+        Err(errno) => unsafe { (*ctx).x[9] = errno.to_ret() as u64 },
+    }
+}
+
+unsafe fn syscall_sigreturn(scheduler: &mut Scheduler, ctx: *mut Context) {
+    let current_task = scheduler.current_task_mut();
+    crate::syscall::syscall_sigreturn(current_task, unsafe { &mut *ctx });
+}
+
 pub unsafe fn syscall(ctx: *mut Context) {
     let syscall_num = unsafe { (*ctx).x[16] };
     let syscall_arg0 = unsafe { (*ctx).x[9] };
-    let syscall_arg1 = unsafe { (*ctx).x[10] };
-    let syscall_arg2 = unsafe { (*ctx).x[11] };
-    let syscall_arg3 = unsafe { (*ctx).x[12] };
+    let syscall_args = unsafe {
+        [
+            (*ctx).x[9],
+            (*ctx).x[10],
+            (*ctx).x[11],
+            (*ctx).x[12],
+            (*ctx).x[13],
+            (*ctx).x[14],
+        ]
+    }; // a0 to a5
 
     let mut scheduler_guard = SCHEDULER.lock();
     let scheduler = unsafe { scheduler_guard.assume_init_mut() };
-    let current_task = scheduler.current_task_mut();
-    if let Some(ret) = dispatch_with_task(
-        current_task,
-        syscall_num,
-        syscall_arg0,
-        syscall_arg1,
-        syscall_arg2,
-        syscall_arg3,
-    ) {
-        unsafe { (*ctx).x[9] = ret };
-    }
-
-    match syscall_num {
-        SYSCALL_EXIT => unsafe {
-            super::trap::kill_task(scheduler, ctx);
-            return;
-        },
-        SYSCALL_FORK => unsafe {
-            syscall_fork(scheduler, ctx);
+    let (syscall_num, ret) = dispatch(scheduler, syscall_num, syscall_args);
+    match ret {
+        Some(ret) if ret == SYSCALL_RET_RESTART as u64 => {
+            unsafe { (*ctx).epc -= 4 }; // execute the syscall again when woken up
+        }
+        Some(ret) => unsafe { (*ctx).x[9] = ret },
+        None => match syscall_num {
+            SYSCALL_EXIT => unsafe {
+                super::trap::kill_task(scheduler, ctx, syscall_arg0 as isize);
+                return;
+            },
+            SYSCALL_FORK => unsafe {
+                syscall_fork(scheduler, ctx);
+            },
+            SYSCALL_EXEC => unsafe {
+                syscall_exec(scheduler, ctx);
+            },
+            SYSCALL_SIGRETURN => unsafe {
+                syscall_sigreturn(scheduler, ctx);
+            },
+            _ => {}
         },
-        _ => {}
     }
 
     let next_task = scheduler.switch_task(ctx);
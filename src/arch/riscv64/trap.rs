@@ -1,7 +1,9 @@
 use super::cpu::Context;
 use crate::{
     arch::riscv64::{page::MODE_SV39, *},
+    backtrace,
     page::{KERNEL_PT, Paging},
+    signal::{SIGILL, SIGSEGV},
     task::{SCHEDULER, Scheduler, Task},
 };
 use core::arch::{asm, global_asm};
@@ -15,15 +17,22 @@ const MCAUSE_ECALL_S: u64 = 9;
 const MCAUSE_ECALL_M: u64 = 11;
 
 const SCAUSE_ILLEGAL_INS: u64 = 2;
+const SCAUSE_SOFT_S: u64 = 1 | INTERRUPT_FLAG;
 const SCAUSE_TIMER_S: u64 = 5 | INTERRUPT_FLAG;
 const SCAUSE_ECALL_U: u64 = 8;
 const SCAUSE_ECALL_S: u64 = 9;
+const SCAUSE_INST_PAGE_FAULT: u64 = 12;
+const SCAUSE_LOAD_PAGE_FAULT: u64 = 13;
+const SCAUSE_STORE_PAGE_FAULT: u64 = 15;
+
+/** Previous privilege level of `sstatus`, set if the trap is taken from supervisor mode */
+const SSTATUS_SPP: u64 = 1 << 8;
 
 pub(super) fn switch_privilege_level(next_task: &Task) {
     if !next_task.is_kernel() {
-        unsafe { asm!("csrc sstatus, {}", in(reg) 1 << 8) }; // set SPP to user mode
+        unsafe { asm!("csrc sstatus, {}", in(reg) SSTATUS_SPP) }; // set SPP to user mode
     } else {
-        unsafe { asm!("csrs sstatus, {}", in(reg) 1 << 8) }; // set SPP to supervisor mode
+        unsafe { asm!("csrs sstatus, {}", in(reg) SSTATUS_SPP) }; // set SPP to supervisor mode
     }
 }
 
@@ -49,15 +58,15 @@ unsafe fn to_kernel_pt() {
 
 /**
  * Kill a task, it is called by trap, and it does:
- * * Remove the task from scheduler.
+ * * Terminate the task with the exit code in scheduler.
  * * Set up the next task's conext.
  * * Switch to the next task's page table.
  */
-pub unsafe fn kill_task(scheduler: &mut Scheduler, ctx: *mut Context) {
+pub unsafe fn kill_task(scheduler: &mut Scheduler, ctx: *mut Context, exit_code: isize) {
     let current_pid = scheduler.current_task().pid;
-    scheduler.kill(current_pid);
+    scheduler.exit(current_pid, exit_code);
 
-    let next_task = scheduler.current_task();
+    let next_task = scheduler.schedule();
     let next_ctx = next_task.context.clone();
     unsafe { ctx.write(next_ctx) };
     super::trap::switch_privilege_level(next_task);
@@ -68,10 +77,29 @@ pub unsafe fn kill_task(scheduler: &mut Scheduler, ctx: *mut Context) {
     }
 }
 
+/** Save the context of the current task, and switch to the next task. */
+unsafe fn switch_task(scheduler: &mut Scheduler, ctx: *mut Context) {
+    let next_task = scheduler.switch_task(ctx);
+    switch_privilege_level(next_task);
+
+    unsafe {
+        next_task.page.switch_to();
+        asm!("sfence.vma");
+    }
+}
+
+/** Record the context of an unexpected trap taken from supervisor mode for the panic backtrace. */
+fn kernel_trap(ctx: *mut Context) {
+    let (pc, fp) = unsafe { ((*ctx).epc, (*ctx).x[7]) }; // s0
+    backtrace::kernel_trap(pc as usize, fp as usize);
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn strap_handler(ctx: *mut Context) {
     let mut scause: u64;
     unsafe { asm!("csrr {}, scause", out(reg) scause) };
+    /* a kernel thread or a syscall running in the kernel */
+    let from_kernel = unsafe { sstatus_r() } & SSTATUS_SPP != 0;
 
     if scause == SCAUSE_ECALL_U || scause == SCAUSE_ECALL_S {
         unsafe { (*ctx).epc += 4 }; // set return address
@@ -82,26 +110,63 @@ pub unsafe extern "C" fn strap_handler(ctx: *mut Context) {
             };
         }
     } else if scause == SCAUSE_TIMER_S {
+        unsafe { to_kernel_pt() };
+
         crate::time::timer();
         super::set_timer(super::TIMER_INTERVAL);
 
-        unsafe { to_kernel_pt() };
-
         let mut scheduler_guard = SCHEDULER.lock();
         let scheduler = unsafe { scheduler_guard.assume_init_mut() };
-        let next_task = scheduler.switch_task(ctx);
-        switch_privilege_level(next_task);
-
+        unsafe { switch_task(scheduler, ctx) };
+    } else if scause == SCAUSE_SOFT_S {
+        /* raised by `yield_now` */
         unsafe {
-            next_task.page.switch_to();
-            asm!("sfence.vma");
+            asm!("csrc sip, 2"); // clear SSIP flag
+            to_kernel_pt();
         }
-    } else if scause == SCAUSE_ILLEGAL_INS {
+
         let mut scheduler_guard = SCHEDULER.lock();
         let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+        unsafe { switch_task(scheduler, ctx) };
+    } else if scause == SCAUSE_INST_PAGE_FAULT
+        || scause == SCAUSE_LOAD_PAGE_FAULT
+        || scause == SCAUSE_STORE_PAGE_FAULT
+    {
+        let mut stval: u64;
         unsafe {
+            asm!("csrr {}, stval", out(reg) stval);
             to_kernel_pt();
-            kill_task(scheduler, ctx);
         }
+
+        let mut scheduler_guard = SCHEDULER.lock();
+        let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+        let current_task = scheduler.current_task_mut();
+        if current_task.handle_page_fault(stval as usize, scause == SCAUSE_STORE_PAGE_FAULT) {
+            unsafe {
+                current_task.page.switch_to();
+                asm!("sfence.vma");
+            }
+        } else {
+            if from_kernel {
+                kernel_trap(ctx);
+                panic!("Kernel page fault at {:#x}", stval);
+            }
+            current_task.signals.force(SIGSEGV);
+            unsafe { switch_task(scheduler, ctx) };
+        }
+    } else if from_kernel {
+        unsafe { to_kernel_pt() };
+        kernel_trap(ctx);
+        if scause == SCAUSE_ILLEGAL_INS {
+            panic!("Kernel illegal instruction");
+        }
+        panic!("Kernel exception, scause = {:#x}", scause);
+    } else if scause == SCAUSE_ILLEGAL_INS {
+        unsafe { to_kernel_pt() };
+
+        let mut scheduler_guard = SCHEDULER.lock();
+        let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+        scheduler.current_task_mut().signals.force(SIGILL);
+        unsafe { switch_task(scheduler, ctx) };
     }
 }
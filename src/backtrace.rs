@@ -0,0 +1,170 @@
+/*!
+ * Kernel backtraces, unwound through frame pointers and symbolized with the function table which
+ * `tools/ksyms` embeds into the `.ksyms` section after linking.
+ */
+
+use crate::{
+    global::Global,
+    kmsg::{KMSG, KernelMessageLevel},
+    page::VIRT_ADDR,
+};
+use alloc::format;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spinlock::Spinlock;
+
+/** Size reserved for the symbol table */
+const KSYMS_SIZE: usize = 1024 * 1024;
+/** `(address: u64, size: u32, name offset: u32)` */
+const KSYMS_ENTRY_SIZE: usize = 16;
+const MAX_FRAMES: usize = 32;
+
+/**
+ * Function symbols sorted by address, as written by `tools/ksyms`: the count as a `u64`, the
+ * entries, then the null-terminated names. All zero if the kernel has not been patched.
+ */
+#[used]
+#[unsafe(link_section = ".ksyms")]
+static KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+/** Context of a fatal kernel trap as `(pc, fp)`, reported instead of the panic handler frames. */
+static TRAP_FRAME: Global<Option<(usize, usize)>> = Spinlock::new(None);
+/** Set by the first backtrace, a fault while unwinding does not unwind again. */
+static UNWINDING: AtomicBool = AtomicBool::new(false);
+
+fn read_le<const N: usize>(table: &[u8], offset: usize) -> [u8; N] {
+    table[offset..offset + N].try_into().unwrap()
+}
+
+/** Find the function containing `addr`, returns its name and start address. */
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    /* the table is patched after compiling, so its content must not be assumed */
+    let table: &'static [u8] = core::hint::black_box(&KSYMS);
+    let count = u64::from_le_bytes(read_le(table, 0)) as usize;
+    let names_offset = count
+        .checked_mul(KSYMS_ENTRY_SIZE)?
+        .checked_add(size_of::<u64>())?;
+    let names = table.get(names_offset..)?;
+    let entry = |index: usize| {
+        let offset = size_of::<u64>() + index * KSYMS_ENTRY_SIZE;
+        (
+            u64::from_le_bytes(read_le(table, offset)) as usize,
+            u32::from_le_bytes(read_le(table, offset + 8)) as usize,
+            u32::from_le_bytes(read_le(table, offset + 12)) as usize,
+        )
+    };
+
+    /* last symbol starting at or below `addr` */
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        if entry(mid).0 <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let (start, size, name_offset) = entry(low.checked_sub(1)?);
+    /* symbols of assembly code have no size */
+    if size != 0 && addr >= start + size {
+        return None;
+    }
+
+    let name = names.get(name_offset..)?;
+    let len = name.iter().position(|byte| *byte == 0)?;
+    Some((core::str::from_utf8(&name[..len]).ok()?, start))
+}
+
+/** Frame pointer of the caller. */
+#[inline(always)]
+fn frame_pointer() -> usize {
+    let fp: usize;
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!("mv {}, s0", out(reg) fp)
+    };
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("mov {}, x29", out(reg) fp)
+    };
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) fp)
+    };
+    fp
+}
+
+/**
+ * Return address and frame pointer saved in the frame of `fp`. On riscv64 they are stored below
+ * `fp`, on arm64 and x86_64 the frame record `(fp, return address)` is at `fp`.
+ */
+unsafe fn frame_record(fp: usize) -> (usize, usize) {
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        (*((fp - 8) as *const usize), *((fp - 16) as *const usize))
+    }
+    #[cfg(any(target_arch = "aarch64", target_arch = "x86_64"))]
+    unsafe {
+        (*((fp + 8) as *const usize), *(fp as *const usize))
+    }
+}
+
+fn print_frame(index: usize, pc: usize, return_addr: bool) {
+    /* a return address may be just after the end of the calling function */
+    let symbol = lookup(if return_addr { pc - 1 } else { pc });
+    let message = match symbol {
+        Some((name, start)) => format!("  #{} {:#x} {}+{:#x}\n", index, pc, name, pc - start),
+        None => format!("  #{} {:#x}\n", index, pc),
+    };
+    KMSG.lock()
+        .add_message(None, KernelMessageLevel::Fatal, message);
+}
+
+/**
+ * Print a backtrace at the fatal level, from `pc` and the chain of frames starting at `fp`. The
+ * walk stops at a null or misaligned frame pointer, or one out of the kernel address space.
+ */
+pub fn print(pc: Option<usize>, mut fp: usize) {
+    if UNWINDING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    KMSG.lock()
+        .add_message(None, KernelMessageLevel::Fatal, "Backtrace:\n");
+    let mut index = 0;
+    if let Some(pc) = pc {
+        print_frame(index, pc, false);
+        index += 1;
+    }
+    while index < MAX_FRAMES && fp >= VIRT_ADDR && fp.is_multiple_of(size_of::<usize>()) {
+        let (return_addr, next_fp) = unsafe { frame_record(fp) };
+        if return_addr == 0 {
+            break;
+        }
+        print_frame(index, return_addr, true);
+        index += 1;
+
+        /* stacks grow down, callers have higher frames */
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+}
+
+/**
+ * Print the backtrace of a panic, from the fatal trap recorded by [kernel_trap] or else from the
+ * caller.
+ */
+#[inline(never)]
+pub fn print_panic() {
+    let trap_frame = TRAP_FRAME.lock().take();
+    match trap_frame {
+        Some((pc, fp)) => print(Some(pc), fp),
+        None => print(None, frame_pointer()),
+    }
+}
+
+/** Record the context of an unexpected kernel trap, which is about to panic. */
+pub fn kernel_trap(pc: usize, fp: usize) {
+    *TRAP_FRAME.lock() = Some((pc, fp));
+}
@@ -0,0 +1,71 @@
+/*!
+ * User and group credentials of tasks.
+ */
+
+use alloc::vec::Vec;
+
+pub const ROOT_UID: usize = 0;
+/** Maximum count of supplementary groups. */
+pub const NGROUPS_MAX: usize = 32;
+
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    /** Real user ID */
+    pub uid: usize,
+    /** Effective user ID, used for permission checks */
+    pub euid: usize,
+    /** Real group ID */
+    pub gid: usize,
+    /** Effective group ID, used for permission checks */
+    pub egid: usize,
+    /** Supplementary group IDs */
+    pub groups: Vec<usize>,
+}
+
+impl Credentials {
+    pub fn is_root(&self) -> bool {
+        self.euid == ROOT_UID
+    }
+    pub fn in_group(&self, gid: usize) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+    /**
+     * Set user IDs, root sets both the real and the effective user ID, other users can only
+     * set the effective user ID to the real user ID.
+     */
+    pub fn set_uid(&mut self, uid: usize) -> bool {
+        if self.is_root() {
+            self.uid = uid;
+            self.euid = uid;
+        } else if uid == self.uid {
+            self.euid = uid;
+        } else {
+            return false;
+        }
+        true
+    }
+    /**
+     * Set group IDs, root sets both the real and the effective group ID, other users can only
+     * set the effective group ID to the real group ID.
+     */
+    pub fn set_gid(&mut self, gid: usize) -> bool {
+        if self.is_root() {
+            self.gid = gid;
+            self.egid = gid;
+        } else if gid == self.gid {
+            self.egid = gid;
+        } else {
+            return false;
+        }
+        true
+    }
+    /** Set supplementary groups, only root can change them. */
+    pub fn set_groups(&mut self, groups: Vec<usize>) -> bool {
+        if self.is_root() && groups.len() <= NGROUPS_MAX {
+            self.groups = groups;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -8,7 +8,7 @@ use crate::{
     lock_uinit,
     path::Path,
     rand::{GLOBAL_RNG, RandomGenerator},
-    vfs::{File, FileSystem, FileType, FsError},
+    vfs::{File, FileSystem, FileType, FsError, Permissions},
 };
 use alloc::{
     collections::BTreeMap,
@@ -28,6 +28,14 @@ pub struct DevFS {
 
 const DEVFS_FILES: [&str; 5] = ["zero", "null", "kmsg", "random", "urandom"];
 
+/** All devices are owned by root. */
+fn dev_permissions(name: &str) -> Permissions {
+    match name {
+        "kmsg" => Permissions::new(0, 0, 0o644),
+        _ => Permissions::new(0, 0, 0o666),
+    }
+}
+
 impl FileSystem for DevFS {
     fn create(&mut self, _path: &Path) -> Result<File, FsError> {
         Err(FsError::NotSupported)
@@ -41,6 +49,7 @@ impl FileSystem for DevFS {
                 return Ok(File {
                     fd,
                     r#type: FileType::CharDev,
+                    perm: dev_permissions(dev),
                 });
             }
         }
@@ -50,6 +59,7 @@ impl FileSystem for DevFS {
             return Ok(File {
                 fd,
                 r#type: FileType::CharDev,
+                perm: dev_permissions(dev),
             });
         }
         Err(FsError::NoSuchFile)
@@ -88,9 +98,9 @@ impl FileSystem for DevFS {
                     Ok(buf_off as u64)
                 }
                 "random" | "urandom" => {
-                    unsafe {
-                        (*(&raw mut GLOBAL_RNG)).assume_init_mut().gen_bytes(buf);
-                    }
+                    let mut rng_guard = GLOBAL_RNG.lock();
+                    let rng = unsafe { rng_guard.assume_init_mut() };
+                    rng.gen_bytes(buf);
                     Ok(buf.len() as u64)
                 }
                 _ => Err(FsError::PermissionDenied), // unreadable device
@@ -0,0 +1,90 @@
+/*!
+ * Kernel error numbers, syscalls return them negated. The values are the same as Linux so both
+ * personalities share them.
+ */
+
+use crate::vfs::{FsError, VfsError};
+use elf::ElfError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(isize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Errno {
+    /** Operation not permitted */
+    EPERM = 1,
+    /** No such file or directory */
+    ENOENT = 2,
+    /** No such process */
+    ESRCH = 3,
+    /** Interrupted by a signal */
+    EINTR = 4,
+    /** I/O error */
+    EIO = 5,
+    /** Argument list too long */
+    E2BIG = 7,
+    /** Exec format error */
+    ENOEXEC = 8,
+    /** Bad file descriptor */
+    EBADF = 9,
+    /** No child process */
+    ECHILD = 10,
+    /** Resource temporarily unavailable */
+    EAGAIN = 11,
+    /** Out of memory */
+    ENOMEM = 12,
+    /** Permission denied */
+    EACCES = 13,
+    /** Bad address */
+    EFAULT = 14,
+    /** Invalid argument */
+    EINVAL = 22,
+    /** Broken pipe */
+    EPIPE = 32,
+    /** File name too long */
+    ENAMETOOLONG = 36,
+    /** Function not implemented */
+    ENOSYS = 38,
+    /** Operation not supported */
+    EOPNOTSUPP = 95,
+    /**
+     * Never returned to user space, the current task has been blocked and the syscall is executed
+     * again once the task is woken up.
+     */
+    ERESTARTSYS = 512,
+}
+
+impl Errno {
+    /** The negative value returned by syscalls. */
+    pub fn to_ret(self) -> isize {
+        -(self as isize)
+    }
+}
+
+impl From<FsError> for Errno {
+    fn from(err: FsError) -> Self {
+        match err {
+            FsError::NoSuchFile => Errno::ENOENT,
+            FsError::NotSupported => Errno::EOPNOTSUPP,
+            FsError::PermissionDenied => Errno::EACCES,
+            FsError::BadAccessMode => Errno::EBADF,
+            FsError::WouldBlock(_) => Errno::EAGAIN,
+            FsError::BrokenPipe => Errno::EPIPE,
+            FsError::Other => Errno::EIO,
+        }
+    }
+}
+
+impl From<VfsError> for Errno {
+    fn from(err: VfsError) -> Self {
+        match err {
+            VfsError::NotMounted => Errno::ENOENT,
+            VfsError::FsError(err) => err.into(),
+        }
+    }
+}
+
+impl From<ElfError> for Errno {
+    fn from(_: ElfError) -> Self {
+        Errno::ENOEXEC
+    }
+}
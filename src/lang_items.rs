@@ -1,5 +1,5 @@
 use crate::{
-    kernel_wait,
+    backtrace, kernel_wait,
     kmsg::{KMSG, KernelMessageLevel},
 };
 use alloc::format;
@@ -19,6 +19,7 @@ fn panic(info: &PanicInfo) -> ! {
             format!("{}\n", info.message()),
         ),
     }
+    backtrace::print_panic();
 
     loop {
         kernel_wait();
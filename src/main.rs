@@ -5,19 +5,29 @@
 
 mod address;
 mod arch;
+mod backtrace;
+mod cred;
 mod devfs;
 mod device;
+mod errno;
 mod global;
 mod kmsg;
 mod lang_items;
 mod mcache;
 mod page;
 mod path;
+mod pipefs;
 mod rand;
+mod reloc;
+mod runqueue;
+mod signal;
+mod smp;
+mod sync;
 mod syscall;
 mod task;
 mod time;
 mod trap;
+mod user_stack;
 mod vfs;
 
 use core::{arch::asm, ptr::addr_of};
@@ -66,6 +76,7 @@ fn cpu_init() {
 
         cpu::cpu_init();
         enable_timer();
+        enable_soft_interrupt();
         #[cfg(feature = "riscv_m_mode")]
         cpu::switch_to_s_level();
     }
@@ -184,8 +195,9 @@ fn load_dtb(dtb_addr: u64) -> Result<DeviceTree, ParseError> {
     }
 }
 
+/** `hart_id` is passed by the SBI on riscv64, it is not used on arm64. */
 #[unsafe(no_mangle)]
-pub extern "C" fn kernel_main(dtb_addr: u64) -> ! {
+pub extern "C" fn kernel_main(dtb_addr: u64, hart_id: u64) -> ! {
     clear_bss();
     cpu_init();
     unsafe {
@@ -204,12 +216,16 @@ pub extern "C" fn kernel_main(dtb_addr: u64) -> ! {
         soc_init(dtb);
     }
 
+    smp::boot_cpu_init(hart_id as usize);
     task::task_init();
     rand::rand_init();
     vfs::vfs_init();
+    #[cfg(debug_assertions)]
+    syscall::linux::boot_check();
     device::device_init();
     if let Ok(dtb) = &dtb {
         setup_console(dtb);
+        smp::smp_init(dtb);
     }
 
     unsafe { trap::enable_interrupts() };
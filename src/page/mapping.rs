@@ -28,6 +28,8 @@ pub trait Entry: Copy {
     fn empty() -> Self;
     fn page_number(&self) -> PhysPage;
     fn is_valid(&self) -> bool;
+    /** Check if a leaf entry grants the access. */
+    fn has_acl(&self, acl: PageACL) -> bool;
 }
 
 #[repr(transparent)]
@@ -116,6 +118,23 @@ pub trait Mapper<E: Entry> {
         current_table.set_entry(vpn & (Self::PTES_PER_DIR - 1), entry);
     }
 
+    /** Walk the tables down to the leaf entry of a virtual page, if it is mapped. */
+    fn translate(&self, vpn: usize) -> Option<E> {
+        let mut current_table = self.root_table();
+
+        for level in (1..Self::LEVEL).rev() {
+            let index = (vpn >> (Self::PTE_BITS * level)) & (Self::PTES_PER_DIR - 1);
+            let entry = current_table.get_entry(index);
+            if !entry.is_valid() {
+                return None;
+            }
+            current_table = Table::from_page(VirtPage::from(entry.page_number()));
+        }
+
+        let entry = current_table.get_entry(vpn & (Self::PTES_PER_DIR - 1));
+        entry.is_valid().then_some(entry)
+    }
+
     /**
      * Map virtual page into physical page with a specified [PageAllocator].
      *
@@ -204,14 +223,19 @@ pub trait Mapper<E: Entry> {
                 page_tables[level].set_entry(index, E::empty());
             } else {
                 let entry = page_tables[level].get_entry(index);
+                if !entry.is_valid() {
+                    return; // not mapped
+                }
                 page_tables[level - 1] = Table::from_page(VirtPage::from(entry.page_number()));
             }
         }
 
-        for table in page_tables.iter().take(Self::LEVEL - 1) {
-            if table.is_empty(Self::PTES_PER_DIR) {
-                let page_start = VirtPage::from(table.page_number()).0;
+        /* free empty tables and remove them from their parents */
+        for level in 0..Self::LEVEL - 1 {
+            if page_tables[level].is_empty(Self::PTES_PER_DIR) {
+                let page_start = VirtPage::from(page_tables[level].page_number()).0;
                 alloc.free_pages(page_start, 1);
+                page_tables[level + 1].set_entry(indexes[level + 1], E::empty());
             } else {
                 break;
             }
@@ -229,6 +253,11 @@ pub trait Mapper<E: Entry> {
         }
     }
 
+    fn unmap(&mut self, vpn: usize, pages: usize) {
+        let alloc_guard = &mut *BUDDY_ALLOCATOR.lock();
+        self.unmap_with_allocator(alloc_guard, vpn, pages);
+    }
+
     fn destroy_with_allocator<A>(&mut self, alloc: &mut A)
     where
         A: PageAllocator,
@@ -5,6 +5,9 @@ use alloc::{
 };
 use core::{borrow::Borrow, ops::Deref, result::Result};
 
+/** Maximum length of a path in bytes, including the null byte */
+pub const PATH_MAX: usize = 4096;
+
 #[repr(transparent)]
 pub struct Path {
     inner: str,
@@ -0,0 +1,152 @@
+/*!
+ * Anonymous pipes, not reachable from any mount point.
+ */
+
+use crate::{
+    path::Path,
+    task::wake_channel,
+    vfs::{File, FileSystem, FileType, FsError, Permissions},
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::result::Result;
+
+const PIPE_BUF_SIZE: usize = 4096;
+
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+impl Pipe {
+    /** Tasks waiting for this pipe are blocked on the address of the pipe. */
+    fn wait_channel(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PipeEnd {
+    Read,
+    Write,
+}
+
+#[derive(Default)]
+pub struct PipeFS {
+    max_fd: u64,
+    /** Boxed to keep wait channels stable. */
+    pipes: BTreeMap<u64, Box<Pipe>>,
+    /** fd => (pipe id, end) */
+    fds: BTreeMap<u64, (u64, PipeEnd)>,
+}
+
+impl PipeFS {
+    fn new_fd(&mut self, pipe_id: u64, end: PipeEnd) -> File {
+        let fd = self.max_fd;
+        self.max_fd += 1;
+        self.fds.insert(fd, (pipe_id, end));
+        File {
+            fd,
+            r#type: FileType::Pipe,
+            perm: Permissions::new(0, 0, 0o600), // never opened by path
+        }
+    }
+}
+
+impl FileSystem for PipeFS {
+    fn create(&mut self, _path: &Path) -> Result<File, FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn open(&mut self, _path: &Path) -> Result<File, FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn read(&mut self, fd: &File, buf: &mut [u8], _offset: u64) -> Result<u64, FsError> {
+        let Some((pipe_id, PipeEnd::Read)) = self.fds.get(&fd.fd).copied() else {
+            return Err(FsError::BadAccessMode); // wrong end of the pipe
+        };
+        let pipe = self.pipes.get_mut(&pipe_id).unwrap();
+
+        if pipe.buf.is_empty() {
+            return if pipe.writers == 0 {
+                Ok(0) // EOF
+            } else {
+                Err(FsError::WouldBlock(pipe.wait_channel()))
+            };
+        }
+
+        let size = core::cmp::min(buf.len(), pipe.buf.len());
+        for (byte, data) in buf.iter_mut().zip(pipe.buf.drain(..size)) {
+            *byte = data;
+        }
+        wake_channel(pipe.wait_channel()); // wake up blocked writers
+        Ok(size as u64)
+    }
+    fn write(&mut self, fd: &File, buf: &[u8], _offset: u64) -> Result<u64, FsError> {
+        let Some((pipe_id, PipeEnd::Write)) = self.fds.get(&fd.fd).copied() else {
+            return Err(FsError::BadAccessMode); // wrong end of the pipe
+        };
+        let pipe = self.pipes.get_mut(&pipe_id).unwrap();
+
+        if pipe.readers == 0 {
+            return Err(FsError::BrokenPipe);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let size = core::cmp::min(buf.len(), PIPE_BUF_SIZE - pipe.buf.len());
+        if size == 0 {
+            return Err(FsError::WouldBlock(pipe.wait_channel()));
+        }
+        pipe.buf.extend(&buf[..size]);
+        wake_channel(pipe.wait_channel()); // wake up blocked readers
+        Ok(size as u64)
+    }
+    fn remove(&mut self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn rename(&mut self, _src: &Path, _dst: &Path) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn close(&mut self, fd: &File) -> Result<(), FsError> {
+        let Some((pipe_id, end)) = self.fds.remove(&fd.fd) else {
+            return Err(FsError::NoSuchFile);
+        };
+        let pipe = self.pipes.get_mut(&pipe_id).unwrap();
+        match end {
+            PipeEnd::Read => pipe.readers -= 1,
+            PipeEnd::Write => pipe.writers -= 1,
+        }
+        /* peers blocked on the pipe see EOF or broken pipe */
+        wake_channel(pipe.wait_channel());
+
+        if pipe.readers == 0 && pipe.writers == 0 {
+            self.pipes.remove(&pipe_id);
+        }
+        Ok(())
+    }
+    fn list_dir(&mut self) -> Result<Vec<String>, FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn pipe(&mut self) -> Result<(File, File), FsError> {
+        let pipe_id = self.max_fd; // identified by the fd of the read end
+        self.pipes.insert(
+            pipe_id,
+            Box::new(Pipe {
+                buf: VecDeque::with_capacity(PIPE_BUF_SIZE),
+                readers: 1,
+                writers: 1,
+            }),
+        );
+        Ok((
+            self.new_fd(pipe_id, PipeEnd::Read),
+            self.new_fd(pipe_id, PipeEnd::Write),
+        ))
+    }
+}
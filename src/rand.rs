@@ -2,7 +2,9 @@
  *  Random generator abstract and implementations.
  */
 
+use crate::global::GlobalUninit;
 use core::{mem::MaybeUninit, ops::Range};
+use spinlock::Spinlock;
 
 const N: usize = 624;
 const M: usize = 397;
@@ -19,12 +21,12 @@ const B: u32 = 0x9d2c5680;
 const C: u32 = 0xefc60000;
 const F: u32 = 1812433253;
 
-pub static mut GLOBAL_RNG: MaybeUninit<MT19937> = MaybeUninit::uninit();
+pub static GLOBAL_RNG: GlobalUninit<MT19937> = Spinlock::new(MaybeUninit::uninit());
 
 const SEED: u32 = 0;
 
 pub fn rand_init() {
-    unsafe { GLOBAL_RNG = MaybeUninit::new(MT19937::new(SEED)) };
+    *GLOBAL_RNG.lock() = MaybeUninit::new(MT19937::new(SEED));
 }
 
 pub trait RandomGenerator {
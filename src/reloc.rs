@@ -0,0 +1,97 @@
+/*!
+ * Relative relocations of position-independent images loaded by the kernel. Symbol relocations
+ * are left to the dynamic loader.
+ */
+
+use crate::errno::Errno;
+use alloc::vec::Vec;
+use elf::{Elf, PType, rela::Rela};
+
+/** Dynamic section tags */
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+
+const DYN_SIZE: usize = 16;
+/** Size of an `Elf64_Rela` */
+const RELA_SIZE: u64 = 24;
+
+#[cfg(target_arch = "riscv64")]
+const R_RELATIVE: u32 = 3; // R_RISCV_RELATIVE
+#[cfg(target_arch = "aarch64")]
+const R_RELATIVE: u32 = 1027; // R_AARCH64_RELATIVE
+#[cfg(target_arch = "x86_64")]
+const R_RELATIVE: u32 = 8; // R_X86_64_RELATIVE
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, Errno> {
+    let bytes = bytes
+        .get(offset..offset + size_of::<u64>())
+        .ok_or(Errno::ENOEXEC)?;
+    Ok(u64::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+/** Offset in the file of `size` bytes at `v_addr`, which must be in the file part of a segment. */
+fn file_offset(elf: &Elf, v_addr: u64, size: u64) -> Result<usize, Errno> {
+    let (v_addr, size) = (v_addr as usize, size as usize);
+    elf.p_headers
+        .iter()
+        .find(|prog| {
+            matches!(prog.p_type, PType::Load)
+                && prog.v_addr <= v_addr
+                && v_addr.saturating_add(size) <= prog.v_addr + prog.p_filesz
+        })
+        .map(|prog| prog.p_offset + v_addr - prog.v_addr)
+        .ok_or(Errno::ENOEXEC)
+}
+
+/**
+ * Apply the `RELA` relative relocations of the dynamic section to the image loaded at `base`.
+ * Relocated words must be in the file part of a segment.
+ */
+pub fn relocate(elf: &Elf, bytes: &mut [u8], base: usize) -> Result<(), Errno> {
+    let Some(dynamic) = elf
+        .p_headers
+        .iter()
+        .find(|prog| matches!(prog.p_type, PType::Dynamic))
+    else {
+        return Ok(());
+    };
+
+    let (mut rela, mut rela_size, mut rela_ent) = (None, 0, RELA_SIZE);
+    for index in 0..dynamic.p_filesz / DYN_SIZE {
+        let offset = dynamic.p_offset + index * DYN_SIZE;
+        let value = read_u64(bytes, offset + size_of::<u64>())?;
+        match read_u64(bytes, offset)? {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => rela_size = value,
+            DT_RELAENT => rela_ent = value,
+            _ => {}
+        }
+    }
+    let Some(rela) = rela else {
+        return Ok(());
+    };
+    if rela_ent < RELA_SIZE {
+        return Err(Errno::ENOEXEC);
+    }
+
+    let rela_offset = file_offset(elf, rela, rela_size)?;
+    let table = bytes
+        .get(rela_offset..rela_offset + rela_size as usize)
+        .ok_or(Errno::ENOEXEC)?;
+    let relas: Vec<Rela> = elf
+        .relas(table, rela_ent as usize)
+        .filter(|rela| rela.r_type == R_RELATIVE)
+        .collect();
+    for rela in relas {
+        let target = file_offset(elf, rela.r_offset as u64, size_of::<u64>() as u64)?;
+        let value = (base as u64).wrapping_add_signed(rela.r_addend);
+        bytes
+            .get_mut(target..target + size_of::<u64>())
+            .ok_or(Errno::ENOEXEC)?
+            .copy_from_slice(&value.to_ne_bytes());
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+/*!
+ * Per-CPU run queues of ready tasks. Fair tasks are ordered by virtual runtime, real-time tasks
+ * by priority and run before fair tasks unless throttled.
+ */
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/** Throttling period of real-time tasks in nanoseconds */
+const RT_PERIOD: u64 = 1_000_000_000;
+/** Runtime of real-time tasks allowed in each period, the rest is left to fair tasks */
+const RT_RUNTIME: u64 = 950_000_000;
+
+#[derive(Default)]
+pub struct RunQueue {
+    /** (vruntime, pid) */
+    tasks: BTreeSet<(usize, usize)>,
+    /**
+     * Monotonic lower bound of the virtual runtimes of the queue, new tasks start from here and
+     * woken up tasks not much before
+     */
+    min_vruntime: usize,
+    /** Ready real-time tasks by priority, tasks of the same priority in FIFO order */
+    rt_tasks: BTreeMap<usize, VecDeque<usize>>,
+    /** Runtime of real-time tasks in the current throttling period */
+    rt_time: u64,
+    /** Start of the current throttling period */
+    rt_period_start: u64,
+    /** Timestamp of the last periodic load balancing of this CPU */
+    pub last_balance: u64,
+}
+
+impl RunQueue {
+    /**
+     * Virtual runtime of new tasks, which is the smallest virtual runtime in the queue but never
+     * goes backwards, so a task cannot gain time by leaving and joining the queue.
+     */
+    pub fn min_vruntime(&self) -> usize {
+        self.min_vruntime
+    }
+    fn update_min_vruntime(&mut self) {
+        if let Some((vruntime, _)) = self.tasks.first() {
+            self.min_vruntime = core::cmp::max(self.min_vruntime, *vruntime);
+        }
+    }
+    /** Count of tasks in the queue, including the running task. */
+    pub fn len(&self) -> usize {
+        self.tasks.len() + self.rt_tasks.values().map(VecDeque::len).sum::<usize>()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn insert(&mut self, vruntime: usize, pid: usize) {
+        self.tasks.insert((vruntime, pid));
+        self.update_min_vruntime();
+    }
+    /** Remove a task from the queue, returns its virtual runtime. */
+    pub fn remove(&mut self, pid: usize) -> Option<usize> {
+        let entry = self
+            .tasks
+            .iter()
+            .find(|(_, this_pid)| *this_pid == pid)
+            .copied()?;
+        self.tasks.remove(&entry);
+        self.update_min_vruntime();
+        Some(entry.0)
+    }
+    /** Task with the smallest virtual runtime, which is the next to run. */
+    pub fn first(&self) -> Option<(usize, usize)> {
+        self.tasks.first().copied()
+    }
+    /** Remove a task from the fair or the real-time queue. */
+    pub fn dequeue(&mut self, pid: usize) {
+        if self.remove_rt(pid).is_none() {
+            self.remove(pid);
+        }
+    }
+    /** Put a real-time task behind the tasks of the same priority. */
+    pub fn insert_rt(&mut self, priority: usize, pid: usize) {
+        self.rt_tasks.entry(priority).or_default().push_back(pid);
+    }
+    /** Remove a real-time task from the queue, returns its priority. */
+    pub fn remove_rt(&mut self, pid: usize) -> Option<usize> {
+        let (priority, tasks) = self
+            .rt_tasks
+            .iter_mut()
+            .find(|(_, tasks)| tasks.contains(&pid))?;
+        let priority = *priority;
+        tasks.retain(|this_pid| *this_pid != pid);
+        if tasks.is_empty() {
+            self.rt_tasks.remove(&priority);
+        }
+        Some(priority)
+    }
+    /** First real-time task of the highest priority. */
+    pub fn first_rt(&self) -> Option<usize> {
+        self.rt_tasks
+            .last_key_value()
+            .and_then(|(_, tasks)| tasks.front().copied())
+    }
+    /** Account runtime of a real-time task to the current throttling period. */
+    pub fn charge_rt(&mut self, runtime: u64) {
+        self.rt_time += runtime;
+    }
+    /**
+     * Check if real-time tasks have used up their runtime of the throttling period, a new period
+     * is started once the current one is over.
+     */
+    pub fn rt_throttled(&mut self, now: u64) -> bool {
+        if now.saturating_sub(self.rt_period_start) >= RT_PERIOD {
+            self.rt_period_start = now;
+            self.rt_time = 0;
+        }
+        self.rt_time >= RT_RUNTIME
+    }
+    /** Fair tasks in descending order of virtual runtime. */
+    pub fn iter_rev(&self) -> impl Iterator<Item = &(usize, usize)> {
+        self.tasks.iter().rev()
+    }
+}
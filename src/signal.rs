@@ -0,0 +1,292 @@
+/*!
+ * POSIX-style signals, signal numbers follow Linux.
+ */
+
+use crate::{arch::Context, task::Task};
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+/** Signal numbers are in `1..NSIG`. */
+pub const NSIG: usize = 32;
+
+/** Special handlers */
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+/** Do not block the signal while its handler is running. */
+pub const SA_NODEFER: u64 = 0x4000_0000;
+/** Reset the action to default once the handler is invoked. */
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/** `how` argument of `sigprocmask` */
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+/** Signals which can never be caught, blocked or ignored. */
+const UNBLOCKABLE: u64 = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+const STOP_SIGNALS: u64 = sig_bit(SIGSTOP) | sig_bit(SIGTSTP) | sig_bit(SIGTTIN) | sig_bit(SIGTTOU);
+
+/** Mask bit of a signal. */
+pub const fn sig_bit(signum: usize) -> u64 {
+    1 << signum
+}
+
+pub fn is_valid(signum: usize) -> bool {
+    (1..NSIG).contains(&signum)
+}
+
+/** Exit code of a task terminated by a signal. */
+pub fn exit_code(signum: usize) -> isize {
+    128 + signum as isize
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+pub fn default_action(signum: usize) -> DefaultAction {
+    match signum {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/** Action of a signal, the layout is shared with user space. */
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct SigAction {
+    /** Handler address, or [SIG_DFL] or [SIG_IGN] */
+    pub handler: u64,
+    pub flags: u64,
+    /** Return address of the handler, which must call `sigreturn` */
+    pub restorer: u64,
+    /** Signals blocked while the handler is running */
+    pub mask: u64,
+}
+
+#[derive(Clone)]
+pub struct SignalState {
+    pub pending: u64,
+    pub blocked: u64,
+    pub actions: [SigAction; NSIG],
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction::default(); NSIG],
+        }
+    }
+}
+
+impl SignalState {
+    /** State inherited by a forked child, pending signals are not inherited. */
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: 0,
+            ..self.clone()
+        }
+    }
+    /** Reset handlers to default on `exec`, ignored signals stay ignored. */
+    pub fn reset_handlers(&mut self) {
+        for action in &mut self.actions {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+    /** Check if the signal is ignored, by its action or by default. */
+    pub fn is_ignored(&self, signum: usize) -> bool {
+        match self.actions[signum].handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(signum) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+    /** Check if a pending signal can be delivered. */
+    pub fn has_deliverable(&self) -> bool {
+        self.pending & !self.blocked != 0
+    }
+    /** Take the lowest pending signal which is not blocked. */
+    pub fn take_next(&mut self) -> Option<usize> {
+        let deliverable = self.pending & !self.blocked;
+        if deliverable == 0 {
+            return None;
+        }
+        let signum = deliverable.trailing_zeros() as usize;
+        self.pending &= !sig_bit(signum);
+        Some(signum)
+    }
+    /**
+     * Mark a signal as pending, a continue signal discards pending stop signals and the other
+     * way round.
+     */
+    pub fn raise(&mut self, signum: usize) {
+        if signum == SIGCONT {
+            self.pending &= !STOP_SIGNALS;
+        } else if STOP_SIGNALS & sig_bit(signum) != 0 {
+            self.pending &= !sig_bit(SIGCONT);
+        }
+        self.pending |= sig_bit(signum);
+    }
+    /** Raise a signal caused by the task itself, which cannot be blocked or ignored. */
+    pub fn force(&mut self, signum: usize) {
+        if self.blocked & sig_bit(signum) != 0 || self.actions[signum].handler == SIG_IGN {
+            self.blocked &= !sig_bit(signum);
+            self.actions[signum] = SigAction::default();
+        }
+        self.raise(signum);
+    }
+    /**
+     * Change the action of a signal, pending signals which become ignored are discarded.
+     *
+     * Returns `false` if the action of the signal cannot be changed.
+     */
+    pub fn set_action(&mut self, signum: usize, action: SigAction) -> bool {
+        if !is_valid(signum) || UNBLOCKABLE & sig_bit(signum) != 0 {
+            return false;
+        }
+        self.actions[signum] = action;
+        if self.is_ignored(signum) {
+            self.pending &= !sig_bit(signum);
+        }
+        true
+    }
+    pub fn set_blocked(&mut self, blocked: u64) {
+        self.blocked = blocked & !UNBLOCKABLE;
+    }
+}
+
+/** Frame pushed onto the user stack when a handler is invoked, restored by `sigreturn`. */
+#[derive(Clone)]
+#[repr(C)]
+struct SignalFrame {
+    context: Context,
+    blocked: u64,
+    /** Deadline of an interrupted sleep, `0` if none */
+    sleep_deadline: u64,
+}
+
+/**
+ * Save the context of the task onto its user stack, and set up the context to run the handler
+ * with the signal number as the argument. Returns `false` if the frame cannot be written.
+ *
+ * Requirements:
+ * * Switched to kernel's page table.
+ */
+pub fn setup_frame(task: &mut Task, signum: usize, action: &SigAction) -> bool {
+    let frame = SignalFrame {
+        context: task.context.clone(),
+        blocked: task.signals.blocked,
+        /* the handler may sleep by itself, the interrupted sleep is restarted after it */
+        sleep_deadline: task.sleep_deadline.take().unwrap_or(0),
+    };
+    let frame_bytes = unsafe {
+        core::slice::from_raw_parts(
+            &frame as *const SignalFrame as *const u8,
+            size_of::<SignalFrame>(),
+        )
+    };
+
+    #[cfg(target_arch = "riscv64")]
+    let user_sp = task.context.x[2] as usize;
+    #[cfg(target_arch = "aarch64")]
+    let user_sp = task.context.sp as usize;
+    let frame_addr = user_sp.wrapping_sub(size_of::<SignalFrame>()) & !0xf; // 16-byte aligned
+
+    if task.copy_to_user(frame_addr, frame_bytes).is_err() {
+        return false;
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    {
+        task.context.epc = action.handler;
+        task.context.x[0] = action.restorer; // ra
+        task.context.x[2] = frame_addr as u64; // sp
+        task.context.x[9] = signum as u64; // a0
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        task.context.elr_el1 = action.handler;
+        task.context.x[30] = action.restorer; // lr
+        task.context.sp = frame_addr as u64;
+        task.context.x[0] = signum as u64;
+    }
+
+    let mut blocked = task.signals.blocked | action.mask;
+    if action.flags & SA_NODEFER == 0 {
+        blocked |= sig_bit(signum);
+    }
+    task.signals.set_blocked(blocked);
+    if action.flags & SA_RESETHAND != 0 {
+        task.signals.actions[signum] = SigAction::default();
+    }
+    true
+}
+
+/**
+ * Restore the context and the blocked mask saved by [setup_frame] from the frame at the stack
+ * pointer of `ctx`. Returns `false` if the frame cannot be read.
+ *
+ * Requirements:
+ * * Switched to kernel's page table.
+ */
+pub fn restore_frame(task: &mut Task, ctx: &mut Context) -> bool {
+    #[cfg(target_arch = "riscv64")]
+    let frame_addr = ctx.x[2] as usize;
+    #[cfg(target_arch = "aarch64")]
+    let frame_addr = ctx.sp as usize;
+
+    let mut frame_bytes = [0; size_of::<SignalFrame>()];
+    if task.copy_from_user(frame_addr, &mut frame_bytes).is_err() {
+        return false;
+    }
+    let frame = unsafe { core::ptr::read_unaligned(frame_bytes.as_ptr() as *const SignalFrame) };
+
+    *ctx = frame.context;
+    #[cfg(target_arch = "aarch64")]
+    {
+        ctx.pstate &= 0xf000_0000; // keep condition flags only, return to EL0
+    }
+    task.signals.set_blocked(frame.blocked);
+    task.sleep_deadline = (frame.sleep_deadline != 0).then_some(frame.sleep_deadline);
+    true
+}
@@ -0,0 +1,240 @@
+/*!
+ * Symmetric multiprocessing. Secondary CPUs are discovered from the `/cpus` node of the device
+ * tree, and started by the boot CPU through SBI HSM on riscv64 or PSCI on arm64.
+ *
+ * Every CPU has its own trap stack, which grows down from the per-CPU area at its top. The
+ * address of the per-CPU area is kept in `sscratch` on riscv64 and in `TPIDR_EL1` on arm64.
+ */
+
+use crate::{
+    address::{PhysAddr, PhysicalAddress, VirtAddr, VirtPage, VirtualAddress, VirtualPage},
+    global::Global,
+    kmsg::KMSG,
+    page::{KERNEL_PT, PAGE_BITS, alloc_pages},
+    task::SCHEDULER,
+    time::get_sys_time,
+};
+use alloc::{boxed::Box, format, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use dtb::{DeviceTree, utils::*};
+use spinlock::Spinlock;
+
+pub const MAX_CPUS: usize = 8;
+pub const TRAP_STACK_PAGES: usize = 16;
+/** CPU mask with all CPUs */
+pub const CPU_MASK_ALL: u64 = u64::MAX;
+/** Stack of the idle task of a secondary CPU */
+const IDLE_STACK_PAGES: usize = 16;
+/** Time in nanoseconds a started CPU has to come online, it is skipped after */
+const START_TIMEOUT: u64 = 1_000_000_000;
+/** Value of [STARTING_CPU] when no CPU is being started */
+const NO_CPU: usize = usize::MAX;
+
+#[repr(C)]
+pub struct PerCpu {
+    /** Logical CPU ID, the boot CPU is `0` */
+    pub id: usize,
+    /** Hart ID on riscv64, affinity fields of `MPIDR_EL1` on arm64 */
+    pub hw_id: usize,
+}
+
+/** Trap stacks of online CPUs, mapped into every user page table. */
+pub static TRAP_STACKS: Global<Vec<VirtPage>> = Spinlock::new(Vec::new());
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(0);
+/**
+ * Logical ID of the CPU being started, claimed either by the CPU when it arrives or by the boot
+ * CPU when it gives up waiting
+ */
+static STARTING_CPU: AtomicUsize = AtomicUsize::new(NO_CPU);
+
+/** Arguments of a secondary CPU, the first two are read before its MMU is enabled. */
+#[repr(C)]
+struct BootArgs {
+    /** Root of the kernel's page table, in the format of `satp` or `TTBRx_EL1` */
+    page_table: u64,
+    stack_top: u64,
+    idle_stack: VirtPage,
+    cpu_id: usize,
+    hw_id: usize,
+}
+
+unsafe extern "C" {
+    /** Entry of secondary CPUs, which is linked at its physical address */
+    #[link_name = "secondary_start_addr"]
+    static SECONDARY_START: u64;
+}
+
+/** Mask bit of a CPU. */
+pub const fn cpu_bit(cpu: usize) -> u64 {
+    1 << cpu
+}
+
+pub fn this_cpu() -> &'static PerCpu {
+    unsafe { &*(crate::trap::per_cpu_ptr() as *const PerCpu) }
+}
+
+pub fn cpu_id() -> usize {
+    this_cpu().id
+}
+
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+/** Allocate the trap stack and the per-CPU area of this CPU. */
+fn per_cpu_init(id: usize, hw_id: usize) {
+    let trap_stack = VirtualPage(alloc_pages(TRAP_STACK_PAGES));
+    let top = (trap_stack.0 + TRAP_STACK_PAGES) << PAGE_BITS;
+    let per_cpu = (top - size_of::<PerCpu>().next_multiple_of(16)) as *mut PerCpu; // 16-byte aligned
+    unsafe {
+        per_cpu.write(PerCpu { id, hw_id });
+        crate::trap::per_cpu_init(per_cpu as usize);
+    }
+    TRAP_STACKS.lock().push(trap_stack);
+}
+
+/**
+ * Set up the per-CPU area of the boot CPU. `hart_id` is passed by the SBI on riscv64, it is
+ * read from `MPIDR_EL1` on arm64.
+ */
+pub fn boot_cpu_init(hart_id: usize) {
+    #[cfg(target_arch = "riscv64")]
+    per_cpu_init(0, hart_id);
+    #[cfg(target_arch = "aarch64")]
+    {
+        let _ = hart_id;
+        per_cpu_init(0, crate::arch::arm64::cpu::mpidr_affinity());
+    }
+    ONLINE_CPUS.store(1, Ordering::Release);
+}
+
+/**
+ * Start secondary CPUs found in the device tree one by one, each of them is online before the
+ * next one is started. A CPU which fails to start or does not come online in time is skipped.
+ *
+ * Requirements:
+ * * Called before any user task is created, as trap stacks are mapped into user page tables.
+ */
+pub fn smp_init(dtb: &DeviceTree) {
+    /* no SBI to start harts in machine mode */
+    if cfg!(feature = "riscv_m_mode") {
+        return;
+    }
+    let Some(cpus) = dtb.root.child_nodes.iter().find(|node| node.name == "cpus") else {
+        return;
+    };
+    #[cfg(target_arch = "aarch64")]
+    let Some(conduit) = crate::arch::arm64::psci::Conduit::from_dtb(dtb) else {
+        return;
+    };
+
+    let boot_hw_id = this_cpu().hw_id;
+    for node in &cpus.child_nodes {
+        if node_name(&node.name) != "cpu"
+            || node
+                .get_property("status")
+                .is_some_and(|status| !status.starts_with(b"okay"))
+        {
+            continue;
+        }
+        let Some(reg) = node.get_property("reg") else {
+            continue;
+        };
+        let hw_id = if node.address_cells == 2 {
+            u64::from_be_bytes(reg[..8].try_into().unwrap())
+        } else {
+            u32::from_be_bytes(reg[..4].try_into().unwrap()) as u64
+        } as usize;
+        if hw_id == boot_hw_id {
+            continue;
+        }
+
+        let cpu_id = online_cpus();
+        if cpu_id == MAX_CPUS {
+            break;
+        }
+        let idle_stack = VirtualPage(alloc_pages(IDLE_STACK_PAGES));
+        #[cfg(target_arch = "riscv64")]
+        let page_table = unsafe { KERNEL_PT.assume_init().0 as u64 }
+            | (crate::arch::riscv64::page::MODE_SV39 << 60);
+        #[cfg(target_arch = "aarch64")]
+        let page_table = unsafe { (KERNEL_PT.assume_init().0 as u64) << PAGE_BITS };
+        let args = Box::new(BootArgs {
+            page_table,
+            stack_top: ((idle_stack.0 + IDLE_STACK_PAGES) << PAGE_BITS) as u64,
+            idle_stack,
+            cpu_id,
+            hw_id,
+        });
+        let args_addr = PhysAddr::from(VirtualAddress(&*args as *const BootArgs as usize)).0 as u64;
+        let entry = unsafe { SECONDARY_START };
+
+        STARTING_CPU.store(cpu_id, Ordering::Release);
+        #[cfg(target_arch = "riscv64")]
+        let ret = crate::arch::riscv64::sbi::hart_start(hw_id as u64, entry, args_addr);
+        #[cfg(target_arch = "aarch64")]
+        let ret = crate::arch::arm64::psci::cpu_on(conduit, hw_id as u64, entry, args_addr);
+        if ret != 0 {
+            KMSG.lock().warning(
+                Some("smp"),
+                format!("Failed to start CPU {:#x}, error {}\n", hw_id, ret),
+            );
+            STARTING_CPU.store(NO_CPU, Ordering::Release);
+            crate::page::free_pages(idle_stack.0, IDLE_STACK_PAGES);
+            continue;
+        }
+        let deadline = get_sys_time() + START_TIMEOUT;
+        while online_cpus() == cpu_id {
+            if get_sys_time() >= deadline
+                && STARTING_CPU
+                    .compare_exchange(cpu_id, NO_CPU, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            {
+                KMSG.lock().warning(
+                    Some("smp"),
+                    format!("CPU {:#x} did not come online, skipped\n", hw_id),
+                );
+                /* the CPU may still arrive later, so its stack and arguments are never freed */
+                core::mem::forget(args);
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/** Entered by secondary CPUs on their idle stacks, with the kernel's page table. */
+#[unsafe(no_mangle)]
+extern "C" fn secondary_main(args_addr: usize) -> ! {
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        crate::arch::riscv64::secondary_cpu_init()
+    };
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        crate::arch::arm64::secondary_cpu_init()
+    };
+
+    let args = unsafe { &*(VirtAddr::from(PhysicalAddress(args_addr)).0 as *const BootArgs) };
+    /* the boot CPU has given up on this CPU */
+    if STARTING_CPU
+        .compare_exchange(args.cpu_id, NO_CPU, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        loop {
+            crate::kernel_wait();
+        }
+    }
+    per_cpu_init(args.cpu_id, args.hw_id);
+    {
+        let mut scheduler_guard = SCHEDULER.lock();
+        let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+        scheduler.add_idle_task(args.cpu_id, args.idle_stack);
+    }
+    ONLINE_CPUS.fetch_add(1, Ordering::Release);
+
+    unsafe { crate::trap::enable_interrupts() };
+    loop {
+        crate::kernel_wait();
+    }
+}
@@ -0,0 +1,211 @@
+/*!
+ * Sleeping synchronization primitives for kernel tasks, a waiting task is parked in the scheduler
+ * instead of spinning like [Spinlock].
+ *
+ * Waiting is only allowed in kernel tasks other than idle tasks, outside trap handlers, with
+ * interrupts enabled and no spinlock held. Waking up can be done from anywhere.
+ */
+
+use crate::{
+    task::{SCHEDULER, TaskState, wake_task},
+    trap::yield_now,
+};
+use alloc::collections::VecDeque;
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use spinlock::Spinlock;
+
+/** Queue of parked tasks, woken up in FIFO order. */
+pub struct WaitQueue {
+    /** PIDs of parked tasks */
+    waiters: Spinlock<VecDeque<usize>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Spinlock::new(VecDeque::new()),
+        }
+    }
+    /**
+     * Park the current task until `condition` returns `true`. The condition is checked with the
+     * queue locked, so a wake up between checking and parking is not lost.
+     */
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            {
+                /* the scheduler is locked before the queue, as in syscalls waking up queues */
+                let mut scheduler_guard = SCHEDULER.lock();
+                let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+                let mut waiters = self.waiters.lock();
+                if condition() {
+                    return;
+                }
+
+                let pid = scheduler.current_task().pid;
+                assert!(!scheduler.is_idle(pid), "The idle task cannot be parked");
+                let current_task = scheduler.current_task_mut();
+                current_task.state = TaskState::Parked;
+                waiters.push_back(current_task.pid);
+            }
+            yield_now();
+        }
+    }
+    /** Wake up the task parked first, returns `false` if there is no parked task. */
+    pub fn wake_one(&self) -> bool {
+        match self.waiters.lock().pop_front() {
+            Some(pid) => {
+                wake_task(pid);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn wake_all(&self) {
+        for pid in core::mem::take(&mut *self.waiters.lock()) {
+            wake_task(pid);
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/** Mutual exclusion lock which parks the task while the lock is held by another task. */
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.locked.swap(true, Ordering::Acquire) {
+            None
+        } else {
+            Some(MutexGuard { mutex: self })
+        }
+    }
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if let Some(guard) = self.try_lock() {
+            return guard;
+        }
+        self.queue
+            .wait_until(|| !self.locked.swap(true, Ordering::Acquire));
+        MutexGuard { mutex: self }
+    }
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.queue.wake_one();
+    }
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+/** Counting semaphore. */
+pub struct Semaphore {
+    count: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+            queue: WaitQueue::new(),
+        }
+    }
+    /** Decrease the count if it is positive, returns `false` otherwise. */
+    pub fn try_down(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+    /** Decrease the count, parks the task until the count is positive. */
+    pub fn down(&self) {
+        self.queue.wait_until(|| self.try_down());
+    }
+    /** Increase the count, and wake up a parked task. */
+    pub fn up(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.queue.wake_one();
+    }
+}
+
+/** Condition variable used with [Mutex], waiting tasks may be woken up spuriously. */
+pub struct Condvar {
+    /** Increased by every notification, so waiters can tell if they have been notified. */
+    seq: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            queue: WaitQueue::new(),
+        }
+    }
+    /** Unlock the mutex and park the task until notified, the mutex is locked again on return. */
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+
+        self.queue
+            .wait_until(|| self.seq.load(Ordering::Acquire) != seq);
+        mutex.lock()
+    }
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.queue.wake_one();
+    }
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.queue.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -2,7 +2,23 @@
  * Definition of syscall numbers and generic implementations.
 */
 
-use crate::{task::Task, vfs::ROOT_VFS};
+use crate::{
+    arch::Context,
+    cred::NGROUPS_MAX,
+    errno::Errno,
+    page::{PAGE_BITS, PAGE_SIZE},
+    path::PATH_MAX,
+    signal::{self, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, SIGPIPE, SigAction},
+    task::{
+        ARG_MAX, MMAP_END, NICE_MAX, NICE_MIN, RT_PRIORITY_MAX, RT_PRIORITY_MIN, SchedPolicy,
+        Scheduler, Task, TaskState,
+    },
+    vfs::{ACCESS_READ, ACCESS_WRITE, FsError, ROOT_VFS},
+};
+use alloc::{string::String, vec::Vec};
+use elf::{ElfAbi, PFlags};
+
+pub mod linux;
 
 pub const SYSCALL_EXIT: u64 = 0;
 pub const SYSCALL_OPEN: u64 = 1;
@@ -13,9 +29,134 @@ pub const SYSCALL_CLOSE: u64 = 5;
 pub const SYSCALL_SLEEP: u64 = 6;
 pub const SYSCALL_FORK: u64 = 7;
 pub const SYSCALL_UNAME: u64 = 8;
+pub const SYSCALL_EXEC: u64 = 9;
+pub const SYSCALL_WAIT: u64 = 10;
+pub const SYSCALL_DUP: u64 = 11;
+pub const SYSCALL_DUP2: u64 = 12;
+pub const SYSCALL_DUP3: u64 = 13;
+pub const SYSCALL_FCNTL: u64 = 14;
+pub const SYSCALL_PIPE: u64 = 15;
+pub const SYSCALL_MMAP: u64 = 16;
+pub const SYSCALL_MUNMAP: u64 = 17;
+pub const SYSCALL_MPROTECT: u64 = 18;
+pub const SYSCALL_BRK: u64 = 19;
+pub const SYSCALL_KILL: u64 = 20;
+pub const SYSCALL_SIGACTION: u64 = 21;
+pub const SYSCALL_SIGPROCMASK: u64 = 22;
+pub const SYSCALL_SIGRETURN: u64 = 23;
+pub const SYSCALL_GETUID: u64 = 24;
+pub const SYSCALL_GETEUID: u64 = 25;
+pub const SYSCALL_GETGID: u64 = 26;
+pub const SYSCALL_SETUID: u64 = 27;
+pub const SYSCALL_SETGID: u64 = 28;
+pub const SYSCALL_GETGROUPS: u64 = 29;
+pub const SYSCALL_SETGROUPS: u64 = 30;
+pub const SYSCALL_NANOSLEEP: u64 = 31;
+pub const SYSCALL_SCHED_SETAFFINITY: u64 = 32;
+pub const SYSCALL_SCHED_GETAFFINITY: u64 = 33;
+pub const SYSCALL_SCHED_YIELD: u64 = 34;
+pub const SYSCALL_SETPRIORITY: u64 = 35;
+pub const SYSCALL_GETPRIORITY: u64 = 36;
+pub const SYSCALL_SCHED_SETSCHEDULER: u64 = 37;
+pub const SYSCALL_SCHED_GETSCHEDULER: u64 = 38;
+pub const SYSCALL_GETEGID: u64 = 39;
 
 pub const SYSCALL_RET_OK: isize = 0;
-pub const SYSCALL_RET_ERR: isize = -1;
+/** Return value of [Errno::ERESTARTSYS], checked by the architecture code. */
+pub const SYSCALL_RET_RESTART: isize = -(Errno::ERESTARTSYS as isize);
+
+/** Return value of a syscall, or the error number which is returned negated. */
+pub type SyscallResult = Result<isize, Errno>;
+
+/** `fcntl` commands */
+pub const F_DUPFD: u64 = 0;
+pub const F_GETFD: u64 = 1;
+pub const F_SETFD: u64 = 2;
+pub const F_DUPFD_CLOEXEC: u64 = 1030;
+
+/** `which` of `setpriority` and `getpriority`, only single tasks are supported. */
+pub const PRIO_PROCESS: u64 = 0;
+
+/** Descriptor flag, close the descriptor on `exec`. */
+pub const FD_CLOEXEC: u64 = 1;
+/** Access modes of `open` */
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_ACCMODE: u64 = 3;
+/** Flag for `dup3` and `pipe`, set close-on-exec flag on the new descriptors. */
+pub const O_CLOEXEC: u64 = 0o2000000;
+
+/** Memory protection flags */
+pub const PROT_NONE: u64 = 0;
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+/** Memory mapping flags */
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+/** Return immediately from `wait` if no child has exited. */
+pub const WAIT_NOHANG: u64 = 1;
+
+/** Syscall numbering and conventions of a task. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Personality {
+    /** The syscalls defined in this module */
+    Native,
+    /** The Linux generic syscall ABI, see [linux] */
+    Linux,
+}
+
+impl Personality {
+    pub fn from_abi(abi: &ElfAbi) -> Self {
+        match abi {
+            ElfAbi::Linux => Self::Linux,
+            _ => Self::Native,
+        }
+    }
+}
+
+/** Value of the return register for a syscall result. */
+pub fn ret_value(result: SyscallResult) -> u64 {
+    match result {
+        Ok(ret) => ret as u64,
+        Err(errno) => errno.to_ret() as u64,
+    }
+}
+
+/**
+ * Dispatch a syscall by the personality of the current task.
+ *
+ * Returns the native syscall number and the return value. The return value is `None` for
+ * syscalls left to the architecture code, which are `exit`, `fork`, `exec` and `sigreturn`
+ * identified by the native syscall number. Unknown syscalls return `-ENOSYS`.
+ */
+pub fn dispatch(scheduler: &mut Scheduler, syscall_num: u64, args: [u64; 6]) -> (u64, Option<u64>) {
+    if scheduler.current_task().personality == Personality::Linux {
+        return linux::dispatch(scheduler, syscall_num, args);
+    }
+    dispatch_native(scheduler, syscall_num, args)
+}
+
+/** Dispatch a native syscall whatever the personality, see [dispatch]. */
+fn dispatch_native(
+    scheduler: &mut Scheduler,
+    syscall_num: u64,
+    args: [u64; 6],
+) -> (u64, Option<u64>) {
+    let [a0, a1, a2, a3, ..] = args;
+    if let SYSCALL_EXIT | SYSCALL_FORK | SYSCALL_EXEC | SYSCALL_SIGRETURN = syscall_num {
+        return (syscall_num, None);
+    }
+    let result = dispatch_with_task(scheduler.current_task_mut(), syscall_num, a0, a1, a2, a3)
+        .or_else(|| dispatch_with_scheduler(scheduler, syscall_num, a0, a1, a2, a3))
+        .unwrap_or(Err(Errno::ENOSYS));
+    (syscall_num, Some(ret_value(result)))
+}
 
 /**
  * Dispatches these syscalls, with the current [Task] struct:
@@ -26,6 +167,26 @@ pub const SYSCALL_RET_ERR: isize = -1;
  * * SYSCALL_CLOSE
  * * SYSCALL_SLEEP
  * * SYSCALL_UNAME
+ * * SYSCALL_DUP
+ * * SYSCALL_DUP2
+ * * SYSCALL_DUP3
+ * * SYSCALL_FCNTL
+ * * SYSCALL_PIPE
+ * * SYSCALL_MMAP
+ * * SYSCALL_MUNMAP
+ * * SYSCALL_MPROTECT
+ * * SYSCALL_BRK
+ * * SYSCALL_SIGACTION
+ * * SYSCALL_SIGPROCMASK
+ * * SYSCALL_GETUID
+ * * SYSCALL_GETEUID
+ * * SYSCALL_GETGID
+ * * SYSCALL_GETEGID
+ * * SYSCALL_SETUID
+ * * SYSCALL_SETGID
+ * * SYSCALL_GETGROUPS
+ * * SYSCALL_SETGROUPS
+ * * SYSCALL_NANOSLEEP
  */
 pub fn dispatch_with_task(
     current_task: &mut Task,
@@ -33,100 +194,836 @@ pub fn dispatch_with_task(
     a0: u64,
     a1: u64,
     a2: u64,
+    a3: u64,
+) -> Option<SyscallResult> {
+    Some(match syscall_num {
+        SYSCALL_OPEN => current_task
+            .copy_user_string(a0 as usize, PATH_MAX)
+            .and_then(|path| syscall_open(current_task, &path, a1)),
+        SYSCALL_READ | SYSCALL_WRITE => syscall_transfer(current_task, syscall_num, a0, a1, a2),
+        SYSCALL_LSEEK => syscall_lseek(current_task, a0, a1),
+        SYSCALL_CLOSE => syscall_close(current_task, a0),
+        SYSCALL_SLEEP => syscall_sleep(current_task, a0),
+        SYSCALL_UNAME => syscall_uname(current_task, a0),
+        SYSCALL_DUP => syscall_dup(current_task, a0),
+        SYSCALL_DUP2 => syscall_dup3(current_task, a0, a1, 0, true),
+        SYSCALL_DUP3 => syscall_dup3(current_task, a0, a1, a2, false),
+        SYSCALL_FCNTL => syscall_fcntl(current_task, a0, a1, a2),
+        SYSCALL_PIPE => syscall_pipe(current_task, a0, a1),
+        SYSCALL_MMAP => syscall_mmap(current_task, a0, a1, a2, a3),
+        SYSCALL_MUNMAP => syscall_munmap(current_task, a0, a1),
+        SYSCALL_MPROTECT => syscall_mprotect(current_task, a0, a1, a2),
+        SYSCALL_BRK => Ok(syscall_brk(current_task, a0)),
+        SYSCALL_SIGACTION => syscall_sigaction(current_task, a0, a1, a2),
+        SYSCALL_SIGPROCMASK => syscall_sigprocmask(current_task, a0, a1, a2),
+        SYSCALL_GETUID => Ok(current_task.cred.uid as isize),
+        SYSCALL_GETEUID => Ok(current_task.cred.euid as isize),
+        SYSCALL_GETGID => Ok(current_task.cred.gid as isize),
+        SYSCALL_GETEGID => Ok(current_task.cred.egid as isize),
+        SYSCALL_SETUID => syscall_setuid(current_task, a0),
+        SYSCALL_SETGID => syscall_setgid(current_task, a0),
+        SYSCALL_GETGROUPS => syscall_getgroups(current_task, a0, a1),
+        SYSCALL_SETGROUPS => syscall_setgroups(current_task, a0, a1),
+        SYSCALL_NANOSLEEP => syscall_nanosleep(current_task, a0, a1),
+        _ => return None,
+    })
+}
+
+/**
+ * Dispatches these syscalls, with the [Scheduler]:
+ * * SYSCALL_WAIT
+ * * SYSCALL_KILL
+ * * SYSCALL_SCHED_SETAFFINITY
+ * * SYSCALL_SCHED_GETAFFINITY
+ * * SYSCALL_SCHED_YIELD
+ * * SYSCALL_SETPRIORITY
+ * * SYSCALL_GETPRIORITY
+ * * SYSCALL_SCHED_SETSCHEDULER
+ * * SYSCALL_SCHED_GETSCHEDULER
+ */
+pub fn dispatch_with_scheduler(
+    scheduler: &mut Scheduler,
+    syscall_num: u64,
+    a0: u64,
+    a1: u64,
+    a2: u64,
     _a3: u64,
-) -> Option<u64> {
-    match syscall_num {
-        SYSCALL_OPEN => {
-            let path = current_task.copy_user_string(a0 as usize);
-            Some(syscall_open(current_task, &path) as u64)
-        }
-        SYSCALL_READ => {
-            let mut buf = alloc::vec![0; a2 as usize];
-            let ret = syscall_read(current_task, a0, &mut buf) as u64;
-            current_task.copy_to_user(a1 as usize, &buf);
-            Some(ret)
-        }
-        SYSCALL_WRITE => {
-            let mut buf = alloc::vec![0; a2 as usize];
-            current_task.copy_from_user(a1 as usize, &mut buf);
-            Some(syscall_write(current_task, a0, &buf) as u64)
-        }
-        SYSCALL_LSEEK => Some(syscall_lseek(current_task, a0, a1) as u64),
-        SYSCALL_CLOSE => {
-            let mut buf = alloc::vec![0; a2 as usize];
-            current_task.copy_from_user(a1 as usize, &mut buf);
-            Some(syscall_close(current_task, a0) as u64)
-        }
-        SYSCALL_SLEEP => {
-            syscall_sleep(current_task, a0);
-            None
+) -> Option<SyscallResult> {
+    Some(match syscall_num {
+        SYSCALL_WAIT => syscall_wait(scheduler, a0 as isize, a1, a2),
+        SYSCALL_KILL => syscall_kill(scheduler, a0, a1),
+        SYSCALL_SCHED_SETAFFINITY => syscall_sched_setaffinity(scheduler, a0, a1, a2),
+        SYSCALL_SCHED_GETAFFINITY => syscall_sched_getaffinity(scheduler, a0, a1, a2),
+        SYSCALL_SCHED_YIELD => syscall_sched_yield(scheduler),
+        SYSCALL_SETPRIORITY => syscall_setpriority(scheduler, a0, a1, a2 as i32 as isize),
+        SYSCALL_GETPRIORITY => syscall_getpriority(scheduler, a0, a1),
+        SYSCALL_SCHED_SETSCHEDULER => syscall_sched_setscheduler(scheduler, a0, a1, a2),
+        SYSCALL_SCHED_GETSCHEDULER => syscall_sched_getscheduler(scheduler, a0),
+        _ => return None,
+    })
+}
+
+/** Open a file with the access mode in `flags`, which is checked against file permissions. */
+pub fn syscall_open(current_task: &mut Task, path: &str, flags: u64) -> SyscallResult {
+    let access = match flags & O_ACCMODE {
+        O_RDONLY => ACCESS_READ,
+        O_WRONLY => ACCESS_WRITE,
+        O_RDWR => ACCESS_READ | ACCESS_WRITE,
+        _ => return Err(Errno::EINVAL),
+    };
+
+    let mut vfs_guard = ROOT_VFS.lock();
+    let vfs = unsafe { vfs_guard.assume_init_mut() };
+    let fd = vfs.open(path, &current_task.cred, access)?;
+    Ok(current_task.fds.add(fd) as isize)
+}
+
+/**
+ * Block the current task on the wait channel if the operation would block, and the syscall is
+ * restarted once woken up.
+ */
+fn fs_result(current_task: &mut Task, result: Result<u64, FsError>) -> SyscallResult {
+    match result {
+        Ok(size) => Ok(size as isize),
+        Err(FsError::WouldBlock(channel)) => {
+            current_task.state = TaskState::Blocked(channel);
+            Err(Errno::ERESTARTSYS)
         }
-        SYSCALL_UNAME => {
-            syscall_uname(current_task, a0);
-            None
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub fn syscall_read(current_task: &mut Task, fd: u64, buf: &mut [u8]) -> SyscallResult {
+    let mut vfs_guard = ROOT_VFS.lock();
+    let vfs = unsafe { vfs_guard.assume_init_mut() };
+    let file = current_task.fds.get(fd as usize).ok_or(Errno::EBADF)?;
+    let result = vfs.read(&mut file.lock(), buf);
+    fs_result(current_task, result)
+}
+
+pub fn syscall_write(current_task: &mut Task, fd: u64, buf: &[u8]) -> SyscallResult {
+    let mut vfs_guard = ROOT_VFS.lock();
+    let vfs = unsafe { vfs_guard.assume_init_mut() };
+    let file = current_task.fds.get(fd as usize).ok_or(Errno::EBADF)?;
+    let result = vfs.write(&mut file.lock(), buf);
+    if let Err(FsError::BrokenPipe) = result {
+        current_task.signals.raise(SIGPIPE);
+    }
+    fs_result(current_task, result)
+}
+
+/**
+ * Native `read` or `write` of `count` bytes at `buf_ptr`, copied through a bounce buffer of at
+ * most a page. The transfer stops at the first short chunk, an error after some bytes is dropped
+ * and the count transferred so far is returned instead.
+ */
+fn syscall_transfer(
+    current_task: &mut Task,
+    syscall_num: u64,
+    fd: u64,
+    buf_ptr: u64,
+    count: u64,
+) -> SyscallResult {
+    let count = count as usize;
+    let mut buf = alloc::vec![0; count.min(PAGE_SIZE)];
+    let mut total = 0;
+    loop {
+        let chunk = &mut buf[..(count - total).min(PAGE_SIZE)];
+        let user_addr = buf_ptr as usize + total;
+        let result = if syscall_num == SYSCALL_READ {
+            syscall_read(current_task, fd, chunk).and_then(|size| {
+                current_task.copy_to_user(user_addr, &chunk[..size as usize])?;
+                Ok(size)
+            })
+        } else {
+            current_task
+                .copy_from_user(user_addr, chunk)
+                .and_then(|_| syscall_write(current_task, fd, chunk))
+        };
+        match result {
+            Ok(size) => {
+                total += size as usize;
+                if (size as usize) < chunk.len() || total == count {
+                    break;
+                }
+            }
+            Err(errno) if total == 0 => return Err(errno),
+            Err(_) => {
+                /* keep the bytes already transferred instead of blocking */
+                current_task.state = TaskState::Ready;
+                break;
+            }
         }
-        _ => None,
     }
+    Ok(total as isize)
+}
+
+pub fn syscall_lseek(current_task: &mut Task, fd: u64, position: u64) -> SyscallResult {
+    let file = current_task.fds.get(fd as usize).ok_or(Errno::EBADF)?;
+    file.lock().offset = position;
+    Ok(SYSCALL_RET_OK)
 }
 
-pub fn syscall_open(current_task: &mut Task, path: &str) -> isize {
+pub fn syscall_close(current_task: &mut Task, fd: u64) -> SyscallResult {
     let mut vfs_guard = ROOT_VFS.lock();
     let vfs = unsafe { vfs_guard.assume_init_mut() };
-    if let Ok(fd) = vfs.open(path) {
-        current_task.fds.add(fd) as isize
-    } else {
-        SYSCALL_RET_ERR
+    let file = current_task.fds.remove(fd as usize).ok_or(Errno::EBADF)?;
+    vfs.release(file)?;
+    Ok(SYSCALL_RET_OK)
+}
+
+pub fn syscall_dup(current_task: &mut Task, fd: u64) -> SyscallResult {
+    match current_task.fds.dup(fd as usize, 0, false) {
+        Some(new_fd) => Ok(new_fd as isize),
+        None => Err(Errno::EBADF),
     }
 }
 
-pub fn syscall_read(current_task: &mut Task, fd: u64, buf: &mut [u8]) -> isize {
+/**
+ * Duplicate `old_fd` to `new_fd`.
+ *
+ * `dup2` is allowed to duplicate a descriptor to itself, which is an error for `dup3`.
+ */
+pub fn syscall_dup3(
+    current_task: &mut Task,
+    old_fd: u64,
+    new_fd: u64,
+    flags: u64,
+    is_dup2: bool,
+) -> SyscallResult {
+    if flags & !O_CLOEXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+    if current_task.fds.get(old_fd as usize).is_none() {
+        return Err(Errno::EBADF);
+    }
+    if old_fd == new_fd {
+        return if is_dup2 {
+            Ok(new_fd as isize)
+        } else {
+            Err(Errno::EINVAL)
+        };
+    }
+
     let mut vfs_guard = ROOT_VFS.lock();
     let vfs = unsafe { vfs_guard.assume_init_mut() };
-    if let Some(fd) = current_task.fds.get_mut(fd as usize)
-        && let Ok(size) = vfs.read(fd, buf)
-    {
-        size as isize
-    } else {
-        SYSCALL_RET_ERR
+    match current_task.fds.dup_to(
+        vfs,
+        old_fd as usize,
+        new_fd as usize,
+        flags & O_CLOEXEC != 0,
+    ) {
+        Some(new_fd) => Ok(new_fd as isize),
+        None => Err(Errno::EBADF),
     }
 }
 
-pub fn syscall_write(current_task: &mut Task, fd: u64, buf: &[u8]) -> isize {
+pub fn syscall_fcntl(current_task: &mut Task, fd: u64, cmd: u64, arg: u64) -> SyscallResult {
+    let fds = &mut current_task.fds;
+    let ret = match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => fds
+            .dup(fd as usize, arg as usize, cmd == F_DUPFD_CLOEXEC)
+            .map(|new_fd| new_fd as isize),
+        F_GETFD => fds
+            .get_cloexec(fd as usize)
+            .map(|cloexec| if cloexec { FD_CLOEXEC as isize } else { 0 }),
+        F_SETFD => fds
+            .set_cloexec(fd as usize, arg & FD_CLOEXEC != 0)
+            .map(|_| SYSCALL_RET_OK),
+        _ => return Err(Errno::EINVAL),
+    };
+    ret.ok_or(Errno::EBADF)
+}
+
+/** Create a pipe, and store the read end and the write end into `fds_ptr` as `int[2]`. */
+pub fn syscall_pipe(current_task: &mut Task, fds_ptr: u64, flags: u64) -> SyscallResult {
+    if flags & !O_CLOEXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+
     let mut vfs_guard = ROOT_VFS.lock();
     let vfs = unsafe { vfs_guard.assume_init_mut() };
-    if let Some(fd) = current_task.fds.get_mut(fd as usize)
-        && let Ok(size) = vfs.write(fd, buf)
-    {
-        size as isize
+    let (read_end, write_end) = vfs.pipe()?;
+    let read_fd = current_task.fds.add(read_end);
+    let write_fd = current_task.fds.add(write_end);
+    if flags & O_CLOEXEC != 0 {
+        current_task.fds.set_cloexec(read_fd, true);
+        current_task.fds.set_cloexec(write_fd, true);
+    }
+
+    let mut fds = [0; 8];
+    fds[..4].copy_from_slice(&(read_fd as i32).to_ne_bytes());
+    fds[4..].copy_from_slice(&(write_fd as i32).to_ne_bytes());
+    if let Err(errno) = current_task.copy_to_user(fds_ptr as usize, &fds) {
+        /* the caller never learns the descriptors, so they must not be left open */
+        for fd in [read_fd, write_fd] {
+            let _ = vfs.release(current_task.fds.remove(fd).unwrap());
+        }
+        return Err(errno);
+    }
+    Ok(SYSCALL_RET_OK)
+}
+
+fn prot_to_flags(prot: u64) -> Option<Vec<PFlags>> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return None;
+    }
+    let mut flags = Vec::new();
+    if prot & PROT_READ != 0 {
+        flags.push(PFlags::Read);
+    }
+    if prot & PROT_WRITE != 0 {
+        flags.push(PFlags::Write);
+    }
+    if prot & PROT_EXEC != 0 {
+        flags.push(PFlags::Exec);
+    }
+    Some(flags)
+}
+
+/**
+ * Check a user memory range, which must end below the user stack, and returns
+ * `(first page, page count)`.
+ */
+fn user_page_range(addr: u64, len: u64) -> Option<(usize, usize)> {
+    let (addr, len) = (addr as usize, len as usize);
+    let end = addr.checked_add(len)?;
+    if addr % PAGE_SIZE != 0 || len == 0 || end > MMAP_END << PAGE_BITS {
+        return None;
+    }
+    Some((addr >> PAGE_BITS, len.div_ceil(PAGE_SIZE)))
+}
+
+/**
+ * Map private anonymous memory, returns the start address of the mapping.
+ */
+pub fn syscall_mmap(
+    current_task: &mut Task,
+    addr: u64,
+    len: u64,
+    prot: u64,
+    flags: u64,
+) -> SyscallResult {
+    if flags & MAP_ANONYMOUS == 0 || flags & MAP_PRIVATE == 0 || flags & MAP_SHARED != 0 {
+        return Err(Errno::EINVAL); // only private anonymous mappings are supported
+    }
+    let p_flags = prot_to_flags(prot).ok_or(Errno::EINVAL)?;
+
+    let fixed = if flags & MAP_FIXED != 0 {
+        let (v_page, _) = user_page_range(addr, len).ok_or(Errno::EINVAL)?;
+        Some(v_page)
+    } else if len == 0 {
+        return Err(Errno::EINVAL);
     } else {
-        SYSCALL_RET_ERR
+        None
+    };
+
+    match current_task.mmap(fixed, (len as usize).div_ceil(PAGE_SIZE), &p_flags) {
+        Some(v_page) => Ok((v_page << PAGE_BITS) as isize),
+        None => Err(Errno::ENOMEM),
     }
 }
 
-pub fn syscall_lseek(current_task: &mut Task, fd: u64, position: u64) -> isize {
-    if let Some(fd) = current_task.fds.get_mut(fd as usize) {
-        fd.offset = position;
-        SYSCALL_RET_OK
+pub fn syscall_munmap(current_task: &mut Task, addr: u64, len: u64) -> SyscallResult {
+    let (v_page, pages) = user_page_range(addr, len).ok_or(Errno::EINVAL)?;
+    match current_task.munmap(v_page, pages) {
+        Some(_) => Ok(SYSCALL_RET_OK),
+        None => Err(Errno::EINVAL),
+    }
+}
+
+/** Change the protection of mapped pages, fails with `ENOMEM` if a page is not mapped. */
+pub fn syscall_mprotect(current_task: &mut Task, addr: u64, len: u64, prot: u64) -> SyscallResult {
+    let (v_page, pages) = user_page_range(addr, len).ok_or(Errno::EINVAL)?;
+    let p_flags = prot_to_flags(prot).ok_or(Errno::EINVAL)?;
+    match current_task.mprotect(v_page, pages, &p_flags) {
+        Some(_) => Ok(SYSCALL_RET_OK),
+        None => Err(Errno::ENOMEM),
+    }
+}
+
+/**
+ * Set the program break, returns the new program break, or the current one on failure.
+ */
+pub fn syscall_brk(current_task: &mut Task, addr: u64) -> isize {
+    current_task.brk(addr as usize) as isize
+}
+
+/**
+ * Send a signal to the task `pid`, `signum` 0 only checks if the task exists. Only root can
+ * send signals to tasks of other users, the real or effective user ID of the sender must match
+ * the real user ID of the target otherwise.
+ */
+pub fn syscall_kill(scheduler: &mut Scheduler, pid: u64, signum: u64) -> SyscallResult {
+    let (pid, signum) = (pid as usize, signum as usize);
+    if signum != 0 && !signal::is_valid(signum) {
+        return Err(Errno::EINVAL);
+    }
+    let cred = &scheduler.current_task().cred;
+    match scheduler.tasks.get(&pid) {
+        Some(task) if task.is_kernel() => return Err(Errno::ESRCH),
+        Some(task) if cred.is_root() || cred.uid == task.cred.uid || cred.euid == task.cred.uid => {
+        }
+        Some(_) => return Err(Errno::EPERM),
+        None => return Err(Errno::ESRCH),
+    }
+
+    if signum == 0 || scheduler.send_signal(pid, signum) {
+        Ok(SYSCALL_RET_OK)
     } else {
-        SYSCALL_RET_ERR
+        Err(Errno::ESRCH)
     }
 }
 
-pub fn syscall_close(current_task: &mut Task, fd: u64) -> isize {
+/**
+ * Find the target of a scheduling syscall, `0` for the current task. Kernel tasks cannot be
+ * targeted, and other users' tasks can only be targeted by root.
+ */
+fn sched_target(scheduler: &Scheduler, pid: u64) -> Result<usize, Errno> {
+    let pid = if pid == 0 {
+        scheduler.current_task().pid
+    } else {
+        pid as usize
+    };
+    let cred = &scheduler.current_task().cred;
+    match scheduler.tasks.get(&pid) {
+        Some(task) if task.is_kernel() => Err(Errno::ESRCH),
+        Some(task) if cred.is_root() || cred.uid == task.cred.uid || cred.euid == task.cred.uid => {
+            Ok(pid)
+        }
+        Some(_) => Err(Errno::EPERM),
+        None => Err(Errno::ESRCH),
+    }
+}
+
+/** Set the CPU mask of a task from `size` bytes at `mask_ptr`, bytes beyond 8 are ignored. */
+pub fn syscall_sched_setaffinity(
+    scheduler: &mut Scheduler,
+    pid: u64,
+    size: u64,
+    mask_ptr: u64,
+) -> SyscallResult {
+    let pid = sched_target(scheduler, pid)?;
+    let mut bytes = [0; size_of::<u64>()];
+    let size = core::cmp::min(size as usize, bytes.len());
+    scheduler
+        .current_task_mut()
+        .copy_from_user(mask_ptr as usize, &mut bytes[..size])?;
+
+    if scheduler.set_affinity(pid, u64::from_ne_bytes(bytes)) {
+        Ok(SYSCALL_RET_OK)
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+/**
+ * Store the CPU mask of a task as 8 bytes at `mask_ptr`, `size` must be at least 8. Returns the
+ * count of stored bytes.
+ */
+pub fn syscall_sched_getaffinity(
+    scheduler: &mut Scheduler,
+    pid: u64,
+    size: u64,
+    mask_ptr: u64,
+) -> SyscallResult {
+    let pid = sched_target(scheduler, pid)?;
+    if (size as usize) < size_of::<u64>() {
+        return Err(Errno::EINVAL);
+    }
+
+    let bytes = scheduler.tasks[&pid].affinity.to_ne_bytes();
+    scheduler
+        .current_task_mut()
+        .copy_to_user(mask_ptr as usize, &bytes)?;
+    Ok(bytes.len() as isize)
+}
+
+/** Move the current task behind the other ready tasks of its CPU. */
+pub fn syscall_sched_yield(scheduler: &mut Scheduler) -> SyscallResult {
+    scheduler.yield_current();
+    Ok(SYSCALL_RET_OK)
+}
+
+/**
+ * Set the nice value of a task, which is clamped to `-20` to `19`. Only root can lower the
+ * nice value.
+ */
+pub fn syscall_setpriority(
+    scheduler: &mut Scheduler,
+    which: u64,
+    who: u64,
+    nice: isize,
+) -> SyscallResult {
+    if which != PRIO_PROCESS {
+        return Err(Errno::EINVAL);
+    }
+    let pid = sched_target(scheduler, who)?;
+    let is_root = scheduler.current_task().cred.is_root();
+    let nice = nice.clamp(NICE_MIN, NICE_MAX);
+
+    let task = scheduler.tasks.get_mut(&pid).unwrap();
+    if nice < task.nice && !is_root {
+        return Err(Errno::EACCES);
+    }
+    task.renice(nice);
+    Ok(SYSCALL_RET_OK)
+}
+
+/** Get the nice value of a task as `20 - nice`, from `1` to `40` so it is never negative. */
+pub fn syscall_getpriority(scheduler: &mut Scheduler, which: u64, who: u64) -> SyscallResult {
+    if which != PRIO_PROCESS {
+        return Err(Errno::EINVAL);
+    }
+    let pid = sched_target(scheduler, who)?;
+    Ok(20 - scheduler.tasks[&pid].nice)
+}
+
+/**
+ * Set the scheduling policy of a task, and its static priority from the `int` at `param_ptr`:
+ * `1` to `99` for `SCHED_FIFO` and `SCHED_RR`, `0` for `SCHED_OTHER`. Only root can set a
+ * real-time policy.
+ */
+pub fn syscall_sched_setscheduler(
+    scheduler: &mut Scheduler,
+    pid: u64,
+    policy: u64,
+    param_ptr: u64,
+) -> SyscallResult {
+    let policy = SchedPolicy::from_raw(policy).ok_or(Errno::EINVAL)?;
+    let pid = sched_target(scheduler, pid)?;
+    let mut bytes = [0; size_of::<i32>()];
+    scheduler
+        .current_task_mut()
+        .copy_from_user(param_ptr as usize, &mut bytes)?;
+    let priority = i32::from_ne_bytes(bytes) as usize;
+
+    let valid = if policy == SchedPolicy::Normal {
+        priority == 0
+    } else {
+        (RT_PRIORITY_MIN..=RT_PRIORITY_MAX).contains(&priority)
+    };
+    if !valid {
+        return Err(Errno::EINVAL);
+    }
+    if policy != SchedPolicy::Normal && !scheduler.current_task().cred.is_root() {
+        return Err(Errno::EPERM);
+    }
+    scheduler.set_scheduler(pid, policy, priority);
+    Ok(SYSCALL_RET_OK)
+}
+
+/** Get the scheduling policy of a task. */
+pub fn syscall_sched_getscheduler(scheduler: &mut Scheduler, pid: u64) -> SyscallResult {
+    let pid = sched_target(scheduler, pid)?;
+    Ok(scheduler.tasks[&pid].policy as isize)
+}
+
+/**
+ * Change the action of a signal to the [SigAction] at `act_ptr` unless it is null, the
+ * previous action is stored into `old_act_ptr` unless it is null.
+ */
+pub fn syscall_sigaction(
+    current_task: &mut Task,
+    signum: u64,
+    act_ptr: u64,
+    old_act_ptr: u64,
+) -> SyscallResult {
+    let signum = signum as usize;
+    if !signal::is_valid(signum) {
+        return Err(Errno::EINVAL);
+    }
+    let old_act = current_task.signals.actions[signum];
+
+    if act_ptr != 0 {
+        let mut act_bytes = [0; size_of::<SigAction>()];
+        current_task.copy_from_user(act_ptr as usize, &mut act_bytes)?;
+        let act = unsafe { core::ptr::read_unaligned(act_bytes.as_ptr() as *const SigAction) };
+        if !current_task.signals.set_action(signum, act) {
+            return Err(Errno::EINVAL);
+        }
+    }
+    if old_act_ptr != 0 {
+        let old_act_bytes = unsafe {
+            core::slice::from_raw_parts(
+                &old_act as *const SigAction as *const u8,
+                size_of::<SigAction>(),
+            )
+        };
+        current_task.copy_to_user(old_act_ptr as usize, old_act_bytes)?;
+    }
+    Ok(SYSCALL_RET_OK)
+}
+
+/**
+ * Change the blocked signal mask with the 64-bit mask at `set_ptr` unless it is null, the
+ * previous mask is stored into `old_set_ptr` unless it is null.
+ */
+pub fn syscall_sigprocmask(
+    current_task: &mut Task,
+    how: u64,
+    set_ptr: u64,
+    old_set_ptr: u64,
+) -> SyscallResult {
+    let old_set = current_task.signals.blocked;
+
+    if set_ptr != 0 {
+        let mut set_bytes = [0; size_of::<u64>()];
+        current_task.copy_from_user(set_ptr as usize, &mut set_bytes)?;
+        let set = u64::from_ne_bytes(set_bytes);
+        let blocked = match how {
+            SIG_BLOCK => old_set | set,
+            SIG_UNBLOCK => old_set & !set,
+            SIG_SETMASK => set,
+            _ => return Err(Errno::EINVAL),
+        };
+        current_task.signals.set_blocked(blocked);
+    }
+    if old_set_ptr != 0 {
+        current_task.copy_to_user(old_set_ptr as usize, &old_set.to_ne_bytes())?;
+    }
+    Ok(SYSCALL_RET_OK)
+}
+
+/**
+ * Return from a signal handler, the context saved when the handler was invoked is restored
+ * into `ctx`. The task gets `SIGSEGV` if the signal frame is invalid.
+ */
+pub fn syscall_sigreturn(current_task: &mut Task, ctx: &mut Context) {
+    if !signal::restore_frame(current_task, ctx) {
+        current_task.signals.force(signal::SIGSEGV);
+    }
+}
+
+pub fn syscall_setuid(current_task: &mut Task, uid: u64) -> SyscallResult {
+    if current_task.cred.set_uid(uid as usize) {
+        Ok(SYSCALL_RET_OK)
+    } else {
+        Err(Errno::EPERM)
+    }
+}
+
+pub fn syscall_setgid(current_task: &mut Task, gid: u64) -> SyscallResult {
+    if current_task.cred.set_gid(gid as usize) {
+        Ok(SYSCALL_RET_OK)
+    } else {
+        Err(Errno::EPERM)
+    }
+}
+
+/**
+ * Store supplementary group IDs as 32-bit integers into `list_ptr`, and returns the count of
+ * groups. Nothing is stored if `size` is 0.
+ */
+pub fn syscall_getgroups(current_task: &mut Task, size: u64, list_ptr: u64) -> SyscallResult {
+    let groups = current_task.cred.groups.clone();
+    if size == 0 {
+        return Ok(groups.len() as isize);
+    }
+    if (size as usize) < groups.len() {
+        return Err(Errno::EINVAL);
+    }
+
+    let bytes = groups
+        .iter()
+        .flat_map(|gid| (*gid as u32).to_ne_bytes())
+        .collect::<Vec<u8>>();
+    current_task.copy_to_user(list_ptr as usize, &bytes)?;
+    Ok(groups.len() as isize)
+}
+
+/** Set supplementary group IDs from `size` 32-bit integers at `list_ptr`. */
+pub fn syscall_setgroups(current_task: &mut Task, size: u64, list_ptr: u64) -> SyscallResult {
+    let size = size as usize;
+    if size > NGROUPS_MAX {
+        return Err(Errno::EINVAL);
+    }
+
+    let mut bytes = alloc::vec![0; size * size_of::<u32>()];
+    current_task.copy_from_user(list_ptr as usize, &mut bytes)?;
+    let groups = bytes
+        .chunks_exact(size_of::<u32>())
+        .map(|gid| u32::from_ne_bytes(gid.try_into().unwrap()) as usize)
+        .collect();
+    if current_task.cred.set_groups(groups) {
+        Ok(SYSCALL_RET_OK)
+    } else {
+        Err(Errno::EPERM)
+    }
+}
+
+/**
+ * Copy a null-terminated array of string pointers, a null `array_ptr` is an empty array. Fails
+ * with `E2BIG` if the strings and the pointers take more than [ARG_MAX] bytes.
+ */
+fn copy_user_args(current_task: &mut Task, array_ptr: u64) -> Result<Vec<String>, Errno> {
+    let mut args = Vec::new();
+    if array_ptr == 0 {
+        return Ok(args);
+    }
+    let mut size = 0;
+    loop {
+        let mut ptr_bytes = [0; size_of::<u64>()];
+        let ptr_addr = array_ptr as usize + args.len() * size_of::<u64>();
+        current_task.copy_from_user(ptr_addr, &mut ptr_bytes)?;
+        let ptr = u64::from_ne_bytes(ptr_bytes);
+        if ptr == 0 {
+            return Ok(args);
+        }
+        let arg = current_task
+            .copy_user_string(ptr as usize, ARG_MAX - size)
+            .map_err(|errno| match errno {
+                Errno::ENAMETOOLONG => Errno::E2BIG,
+                errno => errno,
+            })?;
+        size += arg.len() + 1 + size_of::<u64>();
+        if size > ARG_MAX {
+            return Err(Errno::E2BIG);
+        }
+        args.push(arg);
+    }
+}
+
+/**
+ * Load the program at `path_ptr` into the current task, with the arguments and environment at
+ * `argv_ptr` and `envp_ptr`, both nullable.
+ *
+ * The new context is stored into the current task on success, and it is up to the caller to
+ * restore it.
+ */
+pub fn syscall_exec(
+    scheduler: &mut Scheduler,
+    path_ptr: u64,
+    argv_ptr: u64,
+    envp_ptr: u64,
+) -> SyscallResult {
+    let current_task = scheduler.current_task_mut();
+    let path = current_task.copy_user_string(path_ptr as usize, PATH_MAX)?;
+    let argv = copy_user_args(current_task, argv_ptr)?;
+    let envp = copy_user_args(current_task, envp_ptr)?;
+
+    let elf_bytes = {
+        let mut vfs_guard = ROOT_VFS.lock();
+        let vfs = unsafe { vfs_guard.assume_init_mut() };
+        vfs.read_executable(&path, &scheduler.current_task().cred)?
+    };
+
+    scheduler.exec(&elf_bytes, &argv, &envp)?;
     let mut vfs_guard = ROOT_VFS.lock();
     let vfs = unsafe { vfs_guard.assume_init_mut() };
-    if let Some(fd) = current_task.fds.get(fd as usize)
-        && let Ok(_) = vfs.close(fd)
-    {
-        SYSCALL_RET_OK
-    } else {
-        SYSCALL_RET_ERR
+    scheduler.current_task_mut().fds.close_on_exec(vfs);
+    Ok(SYSCALL_RET_OK)
+}
+
+/**
+ * Wait for a child to exit, `pid` is `-1` for any child. The exit code is stored into
+ * `status_ptr` as a 32-bit integer unless it is null.
+ */
+pub fn syscall_wait(
+    scheduler: &mut Scheduler,
+    pid: isize,
+    status_ptr: u64,
+    options: u64,
+) -> SyscallResult {
+    let Some((child_pid, exit_code)) = wait_child(scheduler, pid, options)? else {
+        return Ok(0);
+    };
+    if status_ptr != 0 {
+        let status = (exit_code as i32).to_ne_bytes();
+        scheduler
+            .current_task_mut()
+            .copy_to_user(status_ptr as usize, &status)?;
+    }
+    Ok(child_pid as isize)
+}
+
+/**
+ * Reap a child for `wait`, `pid` is `-1` for any child. Returns the PID and the exit code of the
+ * child, or `None` if no child has exited and `WAIT_NOHANG` is set.
+ */
+fn wait_child(
+    scheduler: &mut Scheduler,
+    pid: isize,
+    options: u64,
+) -> Result<Option<(usize, isize)>, Errno> {
+    let pid = if pid == -1 { None } else { Some(pid as usize) };
+    match scheduler.reap_child(pid) {
+        Ok(Some(child)) => Ok(Some(child)),
+        Ok(None) if options & WAIT_NOHANG != 0 => Ok(None),
+        Ok(None) => {
+            scheduler.current_task_mut().state = TaskState::WaitChild;
+            Err(Errno::ERESTARTSYS)
+        }
+        Err(()) => Err(Errno::ECHILD),
     }
 }
 
-pub fn syscall_sleep(current_task: &mut Task, timestamp: u64) {
-    let next_time = crate::time::get_sys_time() + timestamp;
-    current_task.next_schedule = Some(next_time);
+/**
+ * Put the task to sleep for `duration` nanoseconds, the syscall is restarted once the task is
+ * woken up by the timer or by a signal.
+ *
+ * Returns the remaining nanoseconds when the restarted syscall finds the sleep has ended, which
+ * is non-zero if it was interrupted, or `None` if the task has just been put to sleep.
+ */
+fn sleep(current_task: &mut Task, duration: u64) -> Option<u64> {
+    let now = crate::time::get_sys_time();
+    if let Some(deadline) = current_task.sleep_deadline.take() {
+        return Some(deadline.saturating_sub(now));
+    }
+    current_task.sleep_deadline = Some(now.saturating_add(duration));
+    current_task.state = TaskState::Sleeping;
+    None
+}
+
+pub fn syscall_sleep(current_task: &mut Task, duration: u64) -> SyscallResult {
+    match sleep(current_task, duration) {
+        None => Err(Errno::ERESTARTSYS),
+        Some(0) => Ok(SYSCALL_RET_OK),
+        Some(_) => Err(Errno::EINTR),
+    }
+}
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/** Layout shared with user space. */
+#[repr(C)]
+struct Timespec {
+    sec: i64,
+    nsec: i64,
+}
+
+pub fn syscall_nanosleep(current_task: &mut Task, req_ptr: u64, rem_ptr: u64) -> SyscallResult {
+    let mut req_bytes = [0; size_of::<Timespec>()];
+    current_task.copy_from_user(req_ptr as usize, &mut req_bytes)?;
+    let req = unsafe { core::ptr::read_unaligned(req_bytes.as_ptr() as *const Timespec) };
+    if req.sec < 0 || !(0..NSEC_PER_SEC as i64).contains(&req.nsec) {
+        return Err(Errno::EINVAL);
+    }
+    let duration = (req.sec as u64)
+        .saturating_mul(NSEC_PER_SEC)
+        .saturating_add(req.nsec as u64);
+
+    match sleep(current_task, duration) {
+        None => Err(Errno::ERESTARTSYS),
+        Some(0) => Ok(SYSCALL_RET_OK),
+        Some(remaining) => {
+            /* interrupted by a signal */
+            if rem_ptr != 0 {
+                let rem = Timespec {
+                    sec: (remaining / NSEC_PER_SEC) as i64,
+                    nsec: (remaining % NSEC_PER_SEC) as i64,
+                };
+                let rem_bytes = unsafe {
+                    core::slice::from_raw_parts(
+                        &rem as *const Timespec as *const u8,
+                        size_of::<Timespec>(),
+                    )
+                };
+                current_task.copy_to_user(rem_ptr as usize, rem_bytes)?;
+            }
+            Err(Errno::EINTR)
+        }
+    }
 }
 
 const UTS_STRING_LEN: usize = 65;
@@ -167,7 +1064,7 @@ const UNAME_MACHINE: &[u8] = b"riscv64";
 #[cfg(target_arch = "x86_64")]
 const UNAME_MACHINE: &[u8] = b"x86_64";
 
-pub fn syscall_uname(current_task: &mut Task, uts_ptr: u64) {
+pub fn syscall_uname(current_task: &mut Task, uts_ptr: u64) -> SyscallResult {
     let mut uts = Utsname::default();
     uts.sysname[..UNAME_SYSNAME.len()].copy_from_slice(UNAME_SYSNAME);
     uts.release[..UNAME_RELEASE.len()].copy_from_slice(UNAME_RELEASE);
@@ -180,5 +1077,6 @@ pub fn syscall_uname(current_task: &mut Task, uts_ptr: u64) {
             core::mem::size_of_val(&uts),
         )
     };
-    current_task.copy_to_user(uts_ptr as usize, uts_bytes);
+    current_task.copy_to_user(uts_ptr as usize, uts_bytes)?;
+    Ok(SYSCALL_RET_OK)
 }
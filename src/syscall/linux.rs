@@ -0,0 +1,420 @@
+/*!
+ * Linux generic syscall ABI of riscv64 and aarch64, used by tasks loaded from ELF images with
+ * the Linux OS ABI. Syscalls are mapped onto the native implementations, which share the Linux
+ * error numbers.
+ */
+
+use super::*;
+
+pub const LINUX_DUP: u64 = 23;
+pub const LINUX_DUP3: u64 = 24;
+pub const LINUX_FCNTL: u64 = 25;
+pub const LINUX_OPENAT: u64 = 56;
+pub const LINUX_CLOSE: u64 = 57;
+pub const LINUX_PIPE2: u64 = 59;
+pub const LINUX_LSEEK: u64 = 62;
+pub const LINUX_READ: u64 = 63;
+pub const LINUX_WRITE: u64 = 64;
+pub const LINUX_READV: u64 = 65;
+pub const LINUX_WRITEV: u64 = 66;
+pub const LINUX_EXIT: u64 = 93;
+pub const LINUX_EXIT_GROUP: u64 = 94;
+pub const LINUX_SET_TID_ADDRESS: u64 = 96;
+pub const LINUX_NANOSLEEP: u64 = 101;
+pub const LINUX_SCHED_SETSCHEDULER: u64 = 119;
+pub const LINUX_SCHED_GETSCHEDULER: u64 = 120;
+pub const LINUX_SCHED_SETAFFINITY: u64 = 122;
+pub const LINUX_SCHED_GETAFFINITY: u64 = 123;
+pub const LINUX_SCHED_YIELD: u64 = 124;
+pub const LINUX_KILL: u64 = 129;
+pub const LINUX_RT_SIGACTION: u64 = 134;
+pub const LINUX_RT_SIGPROCMASK: u64 = 135;
+pub const LINUX_RT_SIGRETURN: u64 = 139;
+pub const LINUX_SETPRIORITY: u64 = 140;
+pub const LINUX_GETPRIORITY: u64 = 141;
+pub const LINUX_SETGID: u64 = 144;
+pub const LINUX_SETUID: u64 = 146;
+pub const LINUX_GETGROUPS: u64 = 158;
+pub const LINUX_SETGROUPS: u64 = 159;
+pub const LINUX_UNAME: u64 = 160;
+pub const LINUX_GETPID: u64 = 172;
+pub const LINUX_GETPPID: u64 = 173;
+pub const LINUX_GETUID: u64 = 174;
+pub const LINUX_GETEUID: u64 = 175;
+pub const LINUX_GETGID: u64 = 176;
+pub const LINUX_GETEGID: u64 = 177;
+pub const LINUX_GETTID: u64 = 178;
+pub const LINUX_BRK: u64 = 214;
+pub const LINUX_MUNMAP: u64 = 215;
+pub const LINUX_CLONE: u64 = 220;
+pub const LINUX_EXECVE: u64 = 221;
+pub const LINUX_MMAP: u64 = 222;
+pub const LINUX_MPROTECT: u64 = 226;
+pub const LINUX_WAIT4: u64 = 260;
+
+/** `dirfd` of `openat` for paths relative to the working directory */
+const AT_FDCWD: i32 = -100;
+/** Signal sent to the parent when the child of `clone` exits */
+const CSIGNAL: u64 = 0xff;
+
+/** `whence` of `lseek` */
+const SEEK_SET: u64 = 0;
+const SEEK_CUR: u64 = 1;
+
+/** Linux `struct utsname` has a `domainname` field after the native fields. */
+const UTS_NATIVE_SIZE: usize = size_of::<Utsname>();
+
+/** Size of `sigset_t`, signal `n` is bit `n - 1` of the set */
+const SIGSET_SIZE: u64 = 8;
+
+/** Maximum count of buffers of `readv` and `writev` */
+const IOV_MAX: u64 = 1024;
+
+/** Buffer of `readv` and `writev` */
+#[repr(C)]
+struct Iovec {
+    base: u64,
+    len: u64,
+}
+
+/**
+ * `struct sigaction` of `rt_sigaction`, which has no restorer on riscv64 where Linux returns
+ * from handlers through the vDSO. There is no vDSO here, so handlers can not be installed there.
+ */
+#[derive(Default)]
+#[repr(C)]
+struct LinuxSigAction {
+    handler: u64,
+    flags: u64,
+    #[cfg(not(target_arch = "riscv64"))]
+    restorer: u64,
+    mask: u64,
+}
+
+impl From<&LinuxSigAction> for SigAction {
+    fn from(act: &LinuxSigAction) -> Self {
+        Self {
+            handler: act.handler,
+            flags: act.flags,
+            #[cfg(not(target_arch = "riscv64"))]
+            restorer: act.restorer,
+            #[cfg(target_arch = "riscv64")]
+            restorer: 0,
+            mask: native_sigset(act.mask),
+        }
+    }
+}
+
+impl From<&SigAction> for LinuxSigAction {
+    fn from(act: &SigAction) -> Self {
+        Self {
+            handler: act.handler,
+            flags: act.flags,
+            #[cfg(not(target_arch = "riscv64"))]
+            restorer: act.restorer,
+            mask: linux_sigset(act.mask),
+        }
+    }
+}
+
+/** Native signal mask of a Linux `sigset_t`, real-time signals are dropped. */
+fn native_sigset(set: u64) -> u64 {
+    (set << 1) & (signal::sig_bit(signal::NSIG) - 1)
+}
+
+/** Linux `sigset_t` of a native signal mask. */
+fn linux_sigset(mask: u64) -> u64 {
+    mask >> 1
+}
+
+/** Native syscalls with the same arguments and return value. */
+fn native_number(syscall_num: u64) -> Option<u64> {
+    Some(match syscall_num {
+        LINUX_DUP => SYSCALL_DUP,
+        LINUX_DUP3 => SYSCALL_DUP3,
+        LINUX_FCNTL => SYSCALL_FCNTL,
+        LINUX_CLOSE => SYSCALL_CLOSE,
+        LINUX_PIPE2 => SYSCALL_PIPE,
+        LINUX_READ => SYSCALL_READ,
+        LINUX_WRITE => SYSCALL_WRITE,
+        LINUX_NANOSLEEP => SYSCALL_NANOSLEEP,
+        LINUX_SCHED_SETSCHEDULER => SYSCALL_SCHED_SETSCHEDULER,
+        LINUX_SCHED_GETSCHEDULER => SYSCALL_SCHED_GETSCHEDULER,
+        LINUX_SCHED_SETAFFINITY => SYSCALL_SCHED_SETAFFINITY,
+        LINUX_SCHED_GETAFFINITY => SYSCALL_SCHED_GETAFFINITY,
+        LINUX_SCHED_YIELD => SYSCALL_SCHED_YIELD,
+        LINUX_KILL => SYSCALL_KILL,
+        LINUX_RT_SIGRETURN => SYSCALL_SIGRETURN,
+        LINUX_SETPRIORITY => SYSCALL_SETPRIORITY,
+        LINUX_GETPRIORITY => SYSCALL_GETPRIORITY,
+        LINUX_SETGID => SYSCALL_SETGID,
+        LINUX_SETUID => SYSCALL_SETUID,
+        LINUX_GETGROUPS => SYSCALL_GETGROUPS,
+        LINUX_SETGROUPS => SYSCALL_SETGROUPS,
+        LINUX_GETUID => SYSCALL_GETUID,
+        LINUX_GETEUID => SYSCALL_GETEUID,
+        LINUX_GETGID => SYSCALL_GETGID,
+        LINUX_GETEGID => SYSCALL_GETEGID,
+        LINUX_BRK => SYSCALL_BRK,
+        LINUX_MUNMAP => SYSCALL_MUNMAP,
+        LINUX_MMAP => SYSCALL_MMAP,
+        LINUX_MPROTECT => SYSCALL_MPROTECT,
+        _ => return None,
+    })
+}
+
+/**
+ * Dispatch a Linux syscall, see [dispatch](super::dispatch). Unknown syscalls return
+ * `-ENOSYS`.
+ */
+pub fn dispatch(scheduler: &mut Scheduler, syscall_num: u64, args: [u64; 6]) -> (u64, Option<u64>) {
+    let [a0, a1, a2, a3, ..] = args;
+    if let Some(native_num) = native_number(syscall_num) {
+        return dispatch_native(scheduler, native_num, args);
+    }
+
+    let current_task = scheduler.current_task_mut();
+    let result = match syscall_num {
+        LINUX_EXIT | LINUX_EXIT_GROUP => return (SYSCALL_EXIT, None),
+        /* only fork-like clones are supported */
+        LINUX_CLONE if a0 & !CSIGNAL == 0 && a1 == 0 => return (SYSCALL_FORK, None),
+        LINUX_CLONE => Err(Errno::EINVAL),
+        LINUX_EXECVE => return (SYSCALL_EXEC, None),
+        LINUX_OPENAT => current_task
+            .copy_user_string(a1 as usize, PATH_MAX)
+            .and_then(|path| syscall_openat(current_task, a0 as i32, &path, a2)),
+        LINUX_LSEEK => syscall_lseek(current_task, a0, a1 as i64, a2),
+        LINUX_RT_SIGACTION => syscall_rt_sigaction(current_task, a0, a1, a2, a3),
+        LINUX_RT_SIGPROCMASK => syscall_rt_sigprocmask(current_task, a0, a1, a2, a3),
+        LINUX_READV => syscall_readv(current_task, a0, a1, a2),
+        LINUX_WRITEV => syscall_writev(current_task, a0, a1, a2),
+        LINUX_UNAME => syscall_uname(current_task, a0),
+        /* threads are not supported, the thread ID is the PID */
+        LINUX_SET_TID_ADDRESS | LINUX_GETPID | LINUX_GETTID => Ok(current_task.pid as isize),
+        LINUX_GETPPID => Ok(current_task.ppid as isize),
+        LINUX_WAIT4 => syscall_wait4(scheduler, a0 as i32 as isize, a1, a2),
+        _ => Err(Errno::ENOSYS),
+    };
+    (syscall_num, Some(ret_value(result)))
+}
+
+/**
+ * Check at boot that Linux syscalls mapped onto native ones reach them: `write` of nothing to a
+ * closed descriptor must fail with `EBADF` from the native `write` rather than `ENOSYS`.
+ */
+#[cfg(debug_assertions)]
+pub fn boot_check() {
+    let mut scheduler_guard = crate::task::SCHEDULER.lock();
+    let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+    let personality = core::mem::replace(
+        &mut scheduler.current_task_mut().personality,
+        Personality::Linux,
+    );
+    let (_, ret) = super::dispatch(scheduler, LINUX_WRITE, [u64::MAX, 0, 0, 0, 0, 0]);
+    scheduler.current_task_mut().personality = personality;
+    assert_eq!(
+        ret,
+        Some(Errno::EBADF.to_ret() as u64),
+        "Linux write does not reach the native write"
+    );
+}
+
+/**
+ * Open a file, `dirfd` must be `AT_FDCWD` for relative paths: other open descriptors fail with
+ * `EOPNOTSUPP` and closed ones with `EBADF`. Creating files is not supported, `O_CLOEXEC` is the
+ * only flag besides the access mode.
+ */
+fn syscall_openat(current_task: &mut Task, dirfd: i32, path: &str, flags: u64) -> SyscallResult {
+    if dirfd != AT_FDCWD && !path.starts_with('/') {
+        return match current_task.fds.get(dirfd as usize) {
+            Some(_) => Err(Errno::EOPNOTSUPP),
+            None => Err(Errno::EBADF),
+        };
+    }
+    let fd = super::syscall_open(current_task, path, flags & O_ACCMODE)?;
+    if flags & O_CLOEXEC != 0 {
+        current_task.fds.set_cloexec(fd as usize, true);
+    }
+    Ok(fd)
+}
+
+/**
+ * Native `sigaction` with a Linux `struct sigaction`. Handlers are called with the signal number
+ * only, even with `SA_SIGINFO`. On riscv64 only the default and ignore actions can be set, as a
+ * handler would have nothing to return to.
+ */
+fn syscall_rt_sigaction(
+    current_task: &mut Task,
+    signum: u64,
+    act_ptr: u64,
+    old_act_ptr: u64,
+    sigset_size: u64,
+) -> SyscallResult {
+    let signum = signum as usize;
+    if sigset_size != SIGSET_SIZE || !signal::is_valid(signum) {
+        return Err(Errno::EINVAL);
+    }
+    let old_act = LinuxSigAction::from(&current_task.signals.actions[signum]);
+
+    if act_ptr != 0 {
+        let mut act_bytes = [0; size_of::<LinuxSigAction>()];
+        current_task.copy_from_user(act_ptr as usize, &mut act_bytes)?;
+        let act = unsafe { core::ptr::read_unaligned(act_bytes.as_ptr() as *const LinuxSigAction) };
+        #[cfg(target_arch = "riscv64")]
+        if act.handler != signal::SIG_DFL && act.handler != signal::SIG_IGN {
+            return Err(Errno::EINVAL);
+        }
+        if !current_task
+            .signals
+            .set_action(signum, SigAction::from(&act))
+        {
+            return Err(Errno::EINVAL);
+        }
+    }
+    if old_act_ptr != 0 {
+        let old_act_bytes = unsafe {
+            core::slice::from_raw_parts(
+                &old_act as *const LinuxSigAction as *const u8,
+                size_of::<LinuxSigAction>(),
+            )
+        };
+        current_task.copy_to_user(old_act_ptr as usize, old_act_bytes)?;
+    }
+    Ok(SYSCALL_RET_OK)
+}
+
+/** Native `sigprocmask` with Linux `sigset_t` masks. */
+fn syscall_rt_sigprocmask(
+    current_task: &mut Task,
+    how: u64,
+    set_ptr: u64,
+    old_set_ptr: u64,
+    sigset_size: u64,
+) -> SyscallResult {
+    if sigset_size != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let old_set = current_task.signals.blocked;
+
+    if set_ptr != 0 {
+        let mut set_bytes = [0; SIGSET_SIZE as usize];
+        current_task.copy_from_user(set_ptr as usize, &mut set_bytes)?;
+        let set = native_sigset(u64::from_ne_bytes(set_bytes));
+        let blocked = match how {
+            SIG_BLOCK => old_set | set,
+            SIG_UNBLOCK => old_set & !set,
+            SIG_SETMASK => set,
+            _ => return Err(Errno::EINVAL),
+        };
+        current_task.signals.set_blocked(blocked);
+    }
+    if old_set_ptr != 0 {
+        current_task.copy_to_user(old_set_ptr as usize, &linux_sigset(old_set).to_ne_bytes())?;
+    }
+    Ok(SYSCALL_RET_OK)
+}
+
+/** Move the offset of a file, returns the new offset. Seeking from the end is not supported. */
+fn syscall_lseek(current_task: &mut Task, fd: u64, offset: i64, whence: u64) -> SyscallResult {
+    let mut file = current_task
+        .fds
+        .get(fd as usize)
+        .ok_or(Errno::EBADF)?
+        .lock();
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.offset,
+        _ => return Err(Errno::EINVAL),
+    };
+    match base.checked_add_signed(offset) {
+        Some(position) if position <= isize::MAX as u64 => {
+            file.offset = position;
+            Ok(position as isize)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/** Native `uname`, followed by an empty `domainname`. */
+fn syscall_uname(current_task: &mut Task, uts_ptr: u64) -> SyscallResult {
+    super::syscall_uname(current_task, uts_ptr)?;
+    let domainname = [0; UTS_STRING_LEN];
+    current_task.copy_to_user(uts_ptr as usize + UTS_NATIVE_SIZE, &domainname)?;
+    Ok(SYSCALL_RET_OK)
+}
+
+/** Read the `iovec` array of `readv` and `writev`. */
+fn read_iovecs(current_task: &mut Task, iov_ptr: u64, iov_count: u64) -> Result<Vec<Iovec>, Errno> {
+    if iov_count > IOV_MAX {
+        return Err(Errno::EINVAL);
+    }
+    let mut bytes = alloc::vec![0; iov_count as usize * size_of::<Iovec>()];
+    current_task.copy_from_user(iov_ptr as usize, &mut bytes)?;
+    Ok(bytes
+        .chunks_exact(size_of::<Iovec>())
+        .map(|chunk| unsafe { core::ptr::read_unaligned(chunk.as_ptr() as *const Iovec) })
+        .collect())
+}
+
+/**
+ * Run the native `read` or `write` on each buffer, until a buffer is not transferred
+ * completely. Returns the total count of bytes, or the error of the first transfer if it fails
+ * or blocks.
+ */
+fn transfer_iovecs(
+    current_task: &mut Task,
+    syscall_num: u64,
+    fd: u64,
+    iovecs: &[Iovec],
+) -> SyscallResult {
+    let mut total = 0;
+    for iovec in iovecs {
+        match dispatch_with_task(current_task, syscall_num, fd, iovec.base, iovec.len, 0).unwrap() {
+            Ok(size) => {
+                total += size;
+                if (size as u64) < iovec.len {
+                    break;
+                }
+            }
+            Err(errno) if total == 0 => return Err(errno),
+            Err(_) => {
+                /* keep the bytes already transferred instead of blocking */
+                current_task.state = TaskState::Ready;
+                break;
+            }
+        }
+    }
+    Ok(total)
+}
+
+fn syscall_readv(current_task: &mut Task, fd: u64, iov_ptr: u64, iov_count: u64) -> SyscallResult {
+    let iovecs = read_iovecs(current_task, iov_ptr, iov_count)?;
+    transfer_iovecs(current_task, SYSCALL_READ, fd, &iovecs)
+}
+
+fn syscall_writev(current_task: &mut Task, fd: u64, iov_ptr: u64, iov_count: u64) -> SyscallResult {
+    let iovecs = read_iovecs(current_task, iov_ptr, iov_count)?;
+    transfer_iovecs(current_task, SYSCALL_WRITE, fd, &iovecs)
+}
+
+/**
+ * Wait for a child, the status is stored in the Linux format where the exit code is in bits 8
+ * to 15. Tasks terminated by a signal are reported as exited with `128` plus the signal number.
+ */
+fn syscall_wait4(
+    scheduler: &mut Scheduler,
+    pid: isize,
+    status_ptr: u64,
+    options: u64,
+) -> SyscallResult {
+    let Some((child_pid, exit_code)) = wait_child(scheduler, pid, options)? else {
+        return Ok(0);
+    };
+    if status_ptr != 0 {
+        let status = (((exit_code & 0xff) as i32) << 8).to_ne_bytes();
+        scheduler
+            .current_task_mut()
+            .copy_to_user(status_ptr as usize, &status)?;
+    }
+    Ok(child_pid as isize)
+}
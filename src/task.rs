@@ -5,12 +5,26 @@
 use crate::{
     address::{PhysPage, VirtPage, VirtualPage},
     arch::{Context, PageMapper},
-    global::GlobalUninit,
+    cred::Credentials,
+    errno::Errno,
+    global::{Global, GlobalUninit},
     page::{
-        KERNEL_PT, PAGE_BITS, PAGE_SIZE, alloc_pages, buddy_allocator::ceil_to_power_2, free_pages,
-        mapping::Mapper,
+        KERNEL_PT, PAGE_BITS, PAGE_SIZE, PageACL, alloc_pages,
+        buddy_allocator::ceil_to_power_2,
+        free_pages,
+        mapping::{Entry, Mapper},
     },
-    vfs::VfsFile,
+    path::PATH_MAX,
+    reloc,
+    runqueue::RunQueue,
+    signal::{
+        self, DefaultAction, SIG_DFL, SIG_IGN, SIGCHLD, SIGCONT, SIGKILL, SIGSEGV, SignalState,
+    },
+    smp::{CPU_MASK_ALL, MAX_CPUS, TRAP_STACK_PAGES, TRAP_STACKS, cpu_bit, cpu_id},
+    syscall::Personality,
+    time::get_sys_time,
+    user_stack::{self, InitialStack},
+    vfs::{OpenFile, ROOT_VFS, VfsFile, VirtualFileSystem},
 };
 use alloc::{
     collections::{BTreeMap, BTreeSet},
@@ -19,152 +33,814 @@ use alloc::{
     vec::Vec,
 };
 use core::mem::MaybeUninit;
-use elf::{Elf, ElfError, PFlags, PType};
+use elf::{Elf, ElfType, PFlags, PType};
 use spinlock::Spinlock;
 
 pub static SCHEDULER: GlobalUninit<Scheduler> = Spinlock::new(MaybeUninit::uninit());
+/** Wait channels woken up since the last schedule. */
+static WOKEN_CHANNELS: Global<Vec<usize>> = Spinlock::new(Vec::new());
+/** Parked tasks woken up since the last schedule. */
+static WOKEN_TASKS: Global<Vec<usize>> = Spinlock::new(Vec::new());
 
 const USER_STACK_PAGES: usize = 16;
+const KERNEL_STACK_PAGES: usize = 16;
+/** Interval of periodic load balancing in nanoseconds */
+const BALANCE_INTERVAL: u64 = 4_000_000;
+/** End of the user stack, the stack grows down from here. */
+const USER_STACK_TOP: usize = 0x40_0000_0000 >> PAGE_BITS;
+/** First virtual page for memory mappings without address hint. */
+const MMAP_BASE: usize = 0x20_0000_0000 >> PAGE_BITS;
+/** End of virtual pages for memory mappings. */
+pub const MMAP_END: usize = USER_STACK_TOP - USER_STACK_PAGES;
+/** Maximum pages of a single memory mapping, which are allocated at once */
+const MMAP_MAX_PAGES: usize = (64 * 1024 * 1024) >> PAGE_BITS;
+/** Load address of position-independent programs */
+const PIE_BASE: usize = 0x10_0000_0000 >> PAGE_BITS;
+/** Load address of program interpreters, the start of the mapping area */
+const INTERP_BASE: usize = MMAP_BASE;
+/** Maximum size of arguments and environment on the initial stack, a quarter of the stack */
+pub const ARG_MAX: usize = (USER_STACK_PAGES << PAGE_BITS) / 4;
 
 #[derive(Default)]
 pub struct Scheduler {
     pub tasks: BTreeMap<usize, Task>,
-    /** (vruntime, pid) */
-    pub vruntime: BTreeSet<(usize, usize)>,
-    current_pid: usize,
+    /** Ready tasks of each CPU, indexed by CPU ID */
+    run_queues: Vec<RunQueue>,
+    /** Exited tasks not yet reaped by their parents. */
+    pub zombies: BTreeMap<usize, Zombie>,
+    /** Sleeping tasks sorted by wake up time, (sleep deadline, pid) */
+    sleep_queue: BTreeSet<(u64, usize)>,
+    /**
+     * Stack of the last exited kernel thread, which may be still in use while the thread exits,
+     * so it is freed when the next kernel thread exits.
+     */
+    dead_kernel_stack: Option<VirtPage>,
+    /** PID of the task running on each CPU, indexed by CPU ID */
+    current_pids: Vec<usize>,
+    /** PID of the idle task of each CPU, indexed by CPU ID */
+    idle_pids: Vec<usize>,
     max_pid: usize,
-    trap_stack: VirtPage,
 }
 
 impl Scheduler {
-    pub fn create_from_elf(&mut self, elf_bytes: &[u8]) -> Result<usize, ElfError> {
+    /**
+     * Build the address space of a user program from an ELF image.
+     *
+     * No memory is allocated for the program, `PT_LOAD` segments and the stack are recorded as
+     * virtual areas which are loaded page by page on first access. Position-independent
+     * programs are loaded at [PIE_BASE]. The interpreter of `PT_INTERP` is loaded at
+     * [INTERP_BASE] and started instead of the program.
+     *
+     * The syscall personality of the program is selected by the OS ABI of the image. Arguments
+     * and environment strings are laid out for the initial stack, see [user_stack]. Fails with
+     * `E2BIG` if they take more than [ARG_MAX] bytes.
+     */
+    fn load_elf(
+        &self,
+        elf_bytes: &[u8],
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<ProgramImage, Errno> {
+        if user_stack::args_size(argv) + user_stack::args_size(envp) > ARG_MAX {
+            return Err(Errno::E2BIG);
+        }
         let elf = Elf::parse(elf_bytes)?;
 
         let mut page = PageMapper::new();
-        let stack = VirtualPage(alloc_pages(USER_STACK_PAGES));
 
         page.map_kernel_region();
-        page.map_data(self.trap_stack.0, PhysPage::from(self.trap_stack).0, 16);
-        page.map_data_u(stack.0, PhysPage::from(stack).0, USER_STACK_PAGES);
-
-        let mut page_allocs = Vec::new();
-        page_allocs.push(Arc::new((
-            stack.0,
-            PhysPage::from(stack),
-            USER_STACK_PAGES,
-            alloc::vec![PFlags::Read, PFlags::Write],
-        )));
-
-        for prog in &elf.p_headers {
-            if let PType::Load = prog.p_type {
-                let v_page = prog.v_addr >> PAGE_BITS;
-                let v_pages = prog.p_memsz.div_ceil(PAGE_SIZE);
-
-                let p_page = PhysPage::from(VirtualPage(alloc_pages(ceil_to_power_2(v_pages))));
-                page_allocs.push(Arc::new((v_page, p_page, v_pages, prog.p_flags.to_vec())));
-                if prog.p_flags.contains(&PFlags::Exec) {
-                    page.map_text_u(v_page, p_page.0, v_pages);
-                } else if prog.p_flags.contains(&PFlags::Write) {
-                    page.map_data_u(v_page, p_page.0, v_pages);
-                } else {
-                    page.map_rodata_u(v_page, p_page.0, v_pages);
-                }
+        map_trap_stacks(&mut page);
 
-                let p_off = prog.v_addr & (PAGE_SIZE - 1); // offset to start of the page
-                unsafe {
-                    core::ptr::copy(
-                        elf_bytes[prog.p_offset..].as_ptr(),
-                        (VirtPage::from(p_page).0 * PAGE_SIZE + p_off) as *mut u8,
-                        prog.p_filesz,
-                    )
-                };
+        let mut areas = Vec::new();
+        areas.push(VmArea {
+            v_page: USER_STACK_TOP - USER_STACK_PAGES,
+            v_pages: USER_STACK_PAGES,
+            flags: alloc::vec![PFlags::Read, PFlags::Write],
+            segment: None,
+        });
+
+        let base = match elf.e_type {
+            ElfType::Exec => 0,
+            ElfType::Dyn => PIE_BASE << PAGE_BITS,
+            _ => return Err(Errno::ENOEXEC),
+        };
+        let brk = load_segments(&elf, elf_bytes, base, &mut areas)?;
+
+        /* program headers are found by `PT_PHDR`, or in the segment loading them */
+        let phdr = elf.p_headers.iter().find_map(|prog| match prog.p_type {
+            PType::Phdr => Some(prog.v_addr),
+            PType::Load
+                if (prog.p_offset..prog.p_offset + prog.p_filesz).contains(&elf.e_phoff) =>
+            {
+                Some(prog.v_addr + elf.e_phoff - prog.p_offset)
+            }
+            _ => None,
+        });
+        let mut entry = base + elf.e_entry;
+        let mut auxv = alloc::vec![
+            (user_stack::AT_PHENT, elf.e_phentsize),
+            (user_stack::AT_PHNUM, elf.p_headers.len()),
+            (user_stack::AT_ENTRY, entry),
+        ];
+        if let Some(phdr) = phdr {
+            auxv.push((user_stack::AT_PHDR, base + phdr));
+        }
+
+        if let Some(interp_path) = interp_path(&elf, elf_bytes)? {
+            let interp_bytes = {
+                let mut vfs_guard = ROOT_VFS.lock();
+                let vfs = unsafe { vfs_guard.assume_init_mut() };
+                vfs.read_executable(&interp_path, &self.current_task().cred)?
+            };
+            let interp = Elf::parse(&interp_bytes)?;
+            if !matches!(interp.e_type, ElfType::Dyn) {
+                return Err(Errno::ENOEXEC);
             }
+            let interp_base = INTERP_BASE << PAGE_BITS;
+            load_segments(&interp, &interp_bytes, interp_base, &mut areas)?;
+            entry = interp_base + interp.e_entry;
+            auxv.push((user_stack::AT_BASE, interp_base));
         }
 
+        let stack = user_stack::build(USER_STACK_TOP << PAGE_BITS, argv, envp, &auxv);
+        let personality = Personality::from_abi(&elf.e_abi);
+
         /* initialize context for each architecture */
         let mut context = Context::default();
         #[cfg(target_arch = "riscv64")]
         {
-            context.epc = elf.e_entry as u64;
-            context.x[2] = ((stack.0 + USER_STACK_PAGES) << PAGE_BITS) as u64; // sp
+            context.epc = entry as u64;
+            context.x[2] = stack.sp as u64;
+            /* native programs also get argc, argv and envp in a0 to a2 */
+            if personality == Personality::Native {
+                context.x[9] = argv.len() as u64;
+                context.x[10] = stack.argv as u64;
+                context.x[11] = stack.envp as u64;
+            }
         }
         #[cfg(target_arch = "aarch64")]
         {
-            context.elr_el1 = elf.e_entry as u64;
-            context.sp = ((stack.0 + USER_STACK_PAGES) << PAGE_BITS) as u64;
+            context.elr_el1 = entry as u64;
+            context.sp = stack.sp as u64;
+            /* native programs also get argc, argv and envp in x0 to x2 */
+            if personality == Personality::Native {
+                context.x[0] = argv.len() as u64;
+                context.x[1] = stack.argv as u64;
+                context.x[2] = stack.envp as u64;
+            }
         }
         #[cfg(target_arch = "x86_64")]
         {
-            context.rsp = ((stack.0 + USER_STACK_PAGES) << PAGE_BITS) as u64;
+            context.rsp = stack.sp as u64;
         }
 
+        Ok(ProgramImage {
+            page,
+            areas,
+            context,
+            brk,
+            personality,
+            stack,
+        })
+    }
+    /**
+     * Create a task running the program of an ELF image, as a child of the current task.
+     *
+     * Requirements:
+     * * Switched to kernel's page table.
+     */
+    pub fn create_from_elf(
+        &mut self,
+        elf_bytes: &[u8],
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<usize, Errno> {
+        let image = self.load_elf(elf_bytes, argv, envp)?;
+
         self.max_pid += 1;
         let pid = self.max_pid;
-        let task = Task {
-            uid: self.current_task().uid,
+        let mut task = Task {
+            cred: self.current_task().cred.clone(),
             pid,
             ppid: self.current_task().pid,
-            page,
+            page: image.page,
             nice: self.current_task().nice,
-            context,
-            page_allocs,
-            next_schedule: None,
+            exec_start: 0,
+            vruntime: 0,
+            policy: self.current_task().policy,
+            rt_priority: self.current_task().rt_priority,
+            rr_runtime: 0,
+            context: image.context,
+            personality: image.personality,
+            page_allocs: Vec::new(),
+            areas: image.areas,
+            brk_start: image.brk,
+            brk: image.brk,
+            sleep_deadline: None,
+            kernel_stack: None,
+            cpu: None,
+            rq_cpu: 0,
+            affinity: CPU_MASK_ALL,
             fds: FdTable::default(),
+            signals: SignalState::default(),
+            state: TaskState::Ready,
         };
+        /* fits in the stack area, checked against ARG_MAX */
+        task.copy_to_user(image.stack.sp, &image.stack.bytes)
+            .unwrap();
         self.tasks.insert(pid, task);
-        let (min_vruntime, _) = self.vruntime.first().unwrap();
-        self.vruntime.insert((*min_vruntime, pid));
+        self.enqueue_new(pid);
 
         Ok(pid)
     }
+    /** PID of the task running on this CPU. */
+    fn current_pid(&self) -> usize {
+        self.current_pids[cpu_id()]
+    }
     pub fn current_task(&self) -> &Task {
-        self.tasks.get(&self.current_pid).unwrap()
+        self.tasks.get(&self.current_pid()).unwrap()
     }
     pub fn current_task_mut(&mut self) -> &mut Task {
-        self.tasks.get_mut(&self.current_pid).unwrap()
+        let pid = self.current_pid();
+        self.tasks.get_mut(&pid).unwrap()
+    }
+    /** Check if the task is the idle task of a CPU, which never blocks. */
+    pub fn is_idle(&self, pid: usize) -> bool {
+        self.idle_pids.contains(&pid)
+    }
+    /**
+     * Add the idle task of a secondary CPU, which is the code running on `stack` when the CPU
+     * comes online. Its PID is reserved from [IDLE_PID_BASE], out of the PIDs of other tasks.
+     */
+    pub fn add_idle_task(&mut self, cpu: usize, stack: VirtPage) {
+        let pid = IDLE_PID_BASE + cpu;
+        let task = Task {
+            cred: Credentials::default(),
+            pid,
+            ppid: KERNEL_PID,
+            page: unsafe { PageMapper::from_root(VirtPage::from(KERNEL_PT.assume_init())) },
+            nice: NICE_DEFAULT,
+            exec_start: 0,
+            vruntime: 0,
+            policy: SchedPolicy::Normal,
+            rt_priority: 0,
+            rr_runtime: 0,
+            context: Context::default(),
+            personality: Personality::Native,
+            page_allocs: Vec::default(),
+            areas: Vec::default(),
+            brk_start: 0,
+            brk: 0,
+            sleep_deadline: None,
+            kernel_stack: Some(stack),
+            cpu: Some(cpu),
+            rq_cpu: cpu,
+            affinity: cpu_bit(cpu),
+            fds: FdTable::default(),
+            signals: SignalState::default(),
+            state: TaskState::Ready,
+        };
+        self.tasks.insert(pid, task);
+        self.run_queues.resize_with(cpu + 1, RunQueue::default);
+        self.run_queues[cpu].insert(0, pid);
+
+        self.current_pids.resize(cpu + 1, KERNEL_PID);
+        self.idle_pids.resize(cpu + 1, KERNEL_PID);
+        self.current_pids[cpu] = pid;
+        self.idle_pids[cpu] = pid;
+    }
+    /** Mask of online CPUs, which have run queues. */
+    fn online_mask(&self) -> u64 {
+        (1 << self.run_queues.len()) - 1
+    }
+    /** Select the CPU with the fewest ready tasks among CPUs in `affinity`. */
+    fn select_cpu(&self, affinity: u64) -> usize {
+        (0..self.run_queues.len())
+            .filter(|cpu| affinity & cpu_bit(*cpu) != 0)
+            .min_by_key(|cpu| self.run_queues[*cpu].len())
+            .unwrap_or(0)
+    }
+    /**
+     * Put a ready task on the run queue of `cpu`, real-time tasks behind the tasks of the same
+     * priority and fair tasks at the minimum virtual runtime.
+     */
+    fn enqueue(&mut self, pid: usize, cpu: usize) {
+        let task = self.tasks.get_mut(&pid).unwrap();
+        if task.is_rt() {
+            task.rq_cpu = cpu;
+            self.run_queues[cpu].insert_rt(task.rt_priority, pid);
+            return;
+        }
+
+        /* the virtual runtime is rebased on the new queue, keeping the lag behind its minimum */
+        let mut vruntime = task.vruntime;
+        if task.rq_cpu != cpu {
+            let lag = vruntime.saturating_sub(self.run_queues[task.rq_cpu].min_vruntime());
+            vruntime = self.run_queues[cpu].min_vruntime() + lag;
+        }
+        let run_queue = &mut self.run_queues[cpu];
+        /* a task which has slept gets at most the slack ahead of the other tasks */
+        vruntime = core::cmp::max(
+            vruntime,
+            run_queue.min_vruntime().saturating_sub(WAKEUP_SLACK),
+        );
+        task.vruntime = vruntime;
+        task.rq_cpu = cpu;
+        run_queue.insert(vruntime, pid);
+    }
+    /** Put a new task on the run queue of the least loaded CPU it is allowed to run on. */
+    fn enqueue_new(&mut self, pid: usize) {
+        let cpu = self.select_cpu(self.tasks[&pid].affinity);
+        let min_vruntime = self.run_queues[cpu].min_vruntime();
+        let task = self.tasks.get_mut(&pid).unwrap();
+        task.vruntime = min_vruntime;
+        task.rq_cpu = cpu;
+        self.enqueue(pid, cpu);
+    }
+    /**
+     * Move a ready task which is not running to the run queue of another CPU, the virtual
+     * runtime of a fair task is rebased on the minimum virtual runtime of the new queue.
+     */
+    fn migrate(&mut self, pid: usize, to: usize) {
+        let task = self.tasks.get_mut(&pid).unwrap();
+        let from = task.rq_cpu;
+        if from == to {
+            return;
+        }
+        if task.is_rt() {
+            if self.run_queues[from].remove_rt(pid).is_some() {
+                self.enqueue(pid, to);
+            }
+            return;
+        }
+        let Some(vruntime) = self.run_queues[from].remove(pid) else {
+            return;
+        };
+        task.vruntime = vruntime;
+        self.enqueue(pid, to);
+    }
+    /**
+     * Pull a task to the run queue of `cpu` from the busiest run queue, if the busiest one has
+     * at least two tasks more. Running tasks and tasks not allowed on `cpu` stay.
+     */
+    fn balance(&mut self, cpu: usize) {
+        let Some(busiest) = (0..self.run_queues.len())
+            .filter(|other| *other != cpu)
+            .max_by_key(|other| self.run_queues[*other].len())
+        else {
+            return;
+        };
+        if self.run_queues[busiest].len() <= self.run_queues[cpu].len() + 1 {
+            return;
+        }
+
+        let movable = self.run_queues[busiest]
+            .iter_rev()
+            .map(|(_, pid)| *pid)
+            .find(|pid| {
+                let task = &self.tasks[pid];
+                task.cpu.is_none() && task.affinity & cpu_bit(cpu) != 0
+            });
+        if let Some(pid) = movable {
+            self.migrate(pid, cpu);
+        }
+    }
+    /** Balance the load of this CPU, called by the timer. */
+    pub fn periodic_balance(&mut self, now: u64) {
+        let cpu = cpu_id();
+        if now.saturating_sub(self.run_queues[cpu].last_balance) >= BALANCE_INTERVAL {
+            self.run_queues[cpu].last_balance = now;
+            self.balance(cpu);
+        }
+    }
+    /**
+     * Set the CPUs a task is allowed to run on, CPUs which are not online are dropped from the
+     * mask. A running task moves to an allowed CPU when it is scheduled out.
+     *
+     * Returns `false` if there is no such task, or no online CPU is in `affinity`.
+     */
+    pub fn set_affinity(&mut self, pid: usize, affinity: u64) -> bool {
+        let affinity = affinity & self.online_mask();
+        let Some(task) = self.tasks.get_mut(&pid) else {
+            return false;
+        };
+        if affinity == 0 {
+            return false;
+        }
+        task.affinity = affinity;
+        if task.cpu.is_none()
+            && task.state == TaskState::Ready
+            && affinity & cpu_bit(task.rq_cpu) == 0
+        {
+            let cpu = self.select_cpu(affinity);
+            self.migrate(pid, cpu);
+        }
+        true
+    }
+    /**
+     * Change the scheduling policy and static priority of a task. A ready task is put back to its
+     * run queue under the new policy.
+     */
+    pub fn set_scheduler(&mut self, pid: usize, policy: SchedPolicy, priority: usize) {
+        let task = self.tasks.get_mut(&pid).unwrap();
+        task.policy = policy;
+        task.rt_priority = priority;
+        task.rr_runtime = 0;
+        if task.state == TaskState::Ready {
+            let cpu = task.rq_cpu;
+            self.run_queues[cpu].dequeue(pid);
+            self.enqueue(pid, cpu);
+        }
+    }
+    /**
+     * Replace the memory image of the current task with a new program, the pid, parent and
+     * open files are kept.
+     *
+     * Requirements:
+     * * Switched to kernel's page table.
+     */
+    pub fn exec(
+        &mut self,
+        elf_bytes: &[u8],
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<(), Errno> {
+        let image = self.load_elf(elf_bytes, argv, envp)?;
+
+        let task = self.current_task_mut();
+        task.release_memory();
+        task.page = image.page;
+        task.areas = image.areas;
+        task.context = image.context;
+        task.personality = image.personality;
+        task.brk_start = image.brk;
+        task.brk = image.brk;
+        task.signals.reset_handlers();
+        /* fits in the stack area, checked against ARG_MAX */
+        task.copy_to_user(image.stack.sp, &image.stack.bytes)
+            .unwrap();
+
+        Ok(())
     }
     /**
-     * Do task schedule, and return the next task.
+     * Do task schedule on the run queue of this CPU, and return the next task. The queue pulls
+     * a task from other CPUs if only the idle task is left. Pending signals of the next task are
+     * handled before it returns to user space.
      */
     pub fn schedule(&mut self) -> &Task {
+        for channel in core::mem::take(&mut *WOKEN_CHANNELS.lock()) {
+            self.wake_channel(channel);
+        }
+        for pid in core::mem::take(&mut *WOKEN_TASKS.lock()) {
+            self.wake(pid);
+        }
+
+        /* charge the current task with its runtime, and take it off the run queue if blocked */
+        let now = get_sys_time();
+        let cpu = cpu_id();
+        let current_pid = self.current_pid();
+        let weight = if current_pid == self.idle_pids[cpu] {
+            IDLE_WEIGHT
+        } else {
+            self.tasks
+                .get(&current_pid)
+                .map_or(NICE_0_WEIGHT, Task::weight)
+        };
+        if let Some(task) = self.tasks.get_mut(&current_pid) {
+            task.cpu = None;
+            let runtime = now.saturating_sub(task.exec_start);
+            let run_queue = &mut self.run_queues[cpu];
+            if task.is_rt() {
+                run_queue.charge_rt(runtime);
+                task.rr_runtime += runtime;
+                /* a round-robin task goes behind the tasks of its priority once its slice is used */
+                if task.policy == SchedPolicy::RoundRobin
+                    && task.rr_runtime >= RR_TIMESLICE
+                    && run_queue.remove_rt(current_pid).is_some()
+                {
+                    task.rr_runtime = 0;
+                    run_queue.insert_rt(task.rt_priority, current_pid);
+                }
+            } else if let Some(vruntime) = run_queue.remove(current_pid) {
+                let delta = (runtime as u128 * NICE_0_WEIGHT as u128 / weight as u128) as usize;
+                task.vruntime = vruntime.saturating_add(delta);
+                run_queue.insert(task.vruntime, current_pid);
+            }
+            if task.state != TaskState::Ready {
+                self.run_queues[cpu].dequeue(current_pid);
+                if task.state == TaskState::Sleeping {
+                    self.sleep_queue
+                        .insert((task.sleep_deadline.unwrap(), current_pid));
+                }
+            } else if task.affinity & cpu_bit(cpu) == 0 {
+                let affinity = task.affinity;
+                let to = self.select_cpu(affinity);
+                self.migrate(current_pid, to);
+            }
+        }
+        if self.run_queues[cpu].len() <= 1 {
+            self.balance(cpu);
+        }
+
+        /* real-time tasks run first, unless they have used up their runtime of the period */
+        let throttled = self.run_queues[cpu].rt_throttled(now);
         loop {
-            let (mut vruntime, pid) = self.vruntime.pop_first().unwrap();
-            let task = self.tasks.get(&pid).unwrap();
-            vruntime += (task.nice + NICE_MAX) as usize; // higher nice -> larger vruntime
-            self.vruntime.insert((vruntime, pid));
-            self.current_pid = pid;
-
-            match self.current_task().next_schedule {
-                Some(next_schedule) => {
-                    if next_schedule <= crate::time::get_sys_time() {
-                        self.current_task_mut().next_schedule = None;
-                        break;
+            let run_queue = &self.run_queues[cpu];
+            let pid = match run_queue.first_rt() {
+                Some(pid) if !throttled => pid,
+                /* the idle task of this CPU is always in the fair queue */
+                _ => run_queue.first().unwrap().1,
+            };
+            self.current_pids[cpu] = pid;
+
+            if self.handle_signals() {
+                break;
+            }
+        }
+
+        let task = self.current_task_mut();
+        task.cpu = Some(cpu);
+        task.exec_start = now;
+        task
+    }
+    /**
+     * Handle pending signals of the current task, and returns `false` if the task has been
+     * terminated or stopped. At most one handler is set up at a time.
+     *
+     * Requirements:
+     * * Switched to kernel's page table.
+     */
+    fn handle_signals(&mut self) -> bool {
+        let pid = self.current_pid();
+        let task = self.current_task_mut();
+        if task.is_kernel() {
+            return true;
+        }
+
+        while let Some(signum) = task.signals.take_next() {
+            let action = task.signals.actions[signum];
+            let default_action = match action.handler {
+                SIG_IGN => continue,
+                SIG_DFL => signal::default_action(signum),
+                _ => {
+                    if signal::setup_frame(task, signum, &action) {
+                        return true;
                     }
+                    DefaultAction::Terminate // the stack is unusable, kill with SIGSEGV
+                }
+            };
+            match default_action {
+                DefaultAction::Ignore | DefaultAction::Continue => continue,
+                DefaultAction::Stop => {
+                    task.state = TaskState::Stopped;
+                    let cpu = task.rq_cpu;
+                    self.run_queues[cpu].dequeue(pid);
+                    return false;
+                }
+                DefaultAction::Terminate => {
+                    let signum = if action.handler == SIG_DFL {
+                        signum
+                    } else {
+                        SIGSEGV
+                    };
+                    self.exit(pid, signal::exit_code(signum));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+    /**
+     * Send a signal to a task, a task blocked in a syscall is woken up if the signal can be
+     * delivered, and the syscall is restarted after the signal is handled. Ignored signals
+     * are discarded, except that `SIGCONT` always continues a stopped task.
+     *
+     * Returns `false` if there is no such task.
+     */
+    pub fn send_signal(&mut self, pid: usize, signum: usize) -> bool {
+        let Some(task) = self.tasks.get_mut(&pid) else {
+            return false;
+        };
+        if task.signals.is_ignored(signum) {
+            if signum == SIGCONT && task.state == TaskState::Stopped {
+                self.wake(pid);
+            }
+            return true;
+        }
+        task.signals.raise(signum);
+
+        let wake = if task.state == TaskState::Stopped {
+            signum == SIGKILL || signum == SIGCONT
+        } else {
+            task.signals.has_deliverable()
+        };
+        /* parked tasks are only woken up by their wait queues */
+        if wake && task.state != TaskState::Parked {
+            self.wake(pid);
+        }
+        true
+    }
+    /**
+     * Put a blocked task back to the run queue of the CPU it ran on, or of another CPU if it is
+     * no longer allowed there. Tasks running on a CPU are still in the run queue until they are
+     * scheduled out, so they are only marked as ready.
+     */
+    pub fn wake(&mut self, pid: usize) {
+        let Some(task) = self.tasks.get_mut(&pid) else {
+            return;
+        };
+        if task.state == TaskState::Ready {
+            return;
+        }
+        if task.state == TaskState::Sleeping {
+            self.sleep_queue
+                .remove(&(task.sleep_deadline.unwrap(), pid));
+        }
+        task.state = TaskState::Ready;
+        if task.cpu.is_some() {
+            return;
+        }
+
+        let cpu = if task.affinity & cpu_bit(task.rq_cpu) == 0 {
+            let affinity = task.affinity;
+            self.select_cpu(affinity)
+        } else {
+            task.rq_cpu
+        };
+        self.enqueue(pid, cpu);
+    }
+    /** Wake up sleeping tasks whose sleep deadline is not later than `now`. */
+    pub fn wake_sleepers(&mut self, now: u64) {
+        while let Some((deadline, pid)) = self.sleep_queue.first().copied()
+            && deadline <= now
+        {
+            self.wake(pid);
+        }
+    }
+    /** Wake up all tasks blocked on a wait channel. */
+    fn wake_channel(&mut self, channel: usize) {
+        let blocked = self
+            .tasks
+            .values()
+            .filter(|task| task.state == TaskState::Blocked(channel))
+            .map(|task| task.pid)
+            .collect::<Vec<usize>>();
+        for pid in blocked {
+            self.wake(pid);
+        }
+    }
+    /**
+     * Terminate a task and keep its exit code as a zombie until the parent reaps it.
+     *
+     * Children of the task are reparented to the init task, or to the kernel task if there is
+     * no init task, in which case they are reaped as soon as they exit.
+     *
+     * If the task is the current task, the caller must [schedule](Scheduler::schedule) another
+     * task.
+     */
+    pub fn exit(&mut self, pid: usize, exit_code: isize) {
+        let Some(mut task) = self.tasks.remove(&pid) else {
+            return;
+        };
+        self.run_queues[task.rq_cpu].dequeue(pid);
+        if let Some(stack) = task.kernel_stack.take()
+            && let Some(dead_stack) = self.dead_kernel_stack.replace(stack)
+        {
+            free_pages(dead_stack.0, KERNEL_STACK_PAGES);
+        }
+        {
+            let mut vfs_guard = ROOT_VFS.lock();
+            let vfs = unsafe { vfs_guard.assume_init_mut() };
+            task.fds.close_all(vfs);
+        }
+
+        let new_parent = if pid != INIT_PID && self.tasks.contains_key(&INIT_PID) {
+            INIT_PID
+        } else {
+            KERNEL_PID
+        };
+        for child in self.tasks.values_mut() {
+            if child.ppid == pid {
+                child.ppid = new_parent;
+            }
+        }
+        if new_parent == KERNEL_PID {
+            self.zombies.retain(|_, zombie| zombie.ppid != pid);
+        } else {
+            let mut has_zombie = false;
+            for zombie in self.zombies.values_mut() {
+                if zombie.ppid == pid {
+                    zombie.ppid = new_parent;
+                    has_zombie = true;
                 }
-                None => break,
             }
+            if has_zombie {
+                self.wake_parent(new_parent);
+            }
+        }
+
+        /* the kernel task never waits, reap immediately */
+        if task.ppid != KERNEL_PID {
+            self.zombies.insert(
+                pid,
+                Zombie {
+                    ppid: task.ppid,
+                    exit_code,
+                },
+            );
+            self.wake_parent(task.ppid);
+            self.send_signal(task.ppid, SIGCHLD);
+        }
+    }
+    fn wake_parent(&mut self, ppid: usize) {
+        if let Some(parent) = self.tasks.get(&ppid)
+            && parent.state == TaskState::WaitChild
+        {
+            self.wake(ppid);
+        }
+    }
+    /**
+     * Reap an exited child of the current task, `pid` is `None` for any child.
+     *
+     * Returns `Ok(Some((pid, exit_code)))` if a child is reaped, `Ok(None)` if children are
+     * still running, and `Err(())` if there is no such child.
+     */
+    pub fn reap_child(&mut self, pid: Option<usize>) -> Result<Option<(usize, isize)>, ()> {
+        let current_pid = self.current_pid();
+        let is_target = |child_pid: usize, ppid: usize| {
+            ppid == current_pid && pid.is_none_or(|pid| pid == child_pid)
+        };
+
+        let zombie_pid = self
+            .zombies
+            .iter()
+            .find(|(child_pid, zombie)| is_target(**child_pid, zombie.ppid))
+            .map(|(child_pid, _)| *child_pid);
+        if let Some(child_pid) = zombie_pid {
+            let zombie = self.zombies.remove(&child_pid).unwrap();
+            return Ok(Some((child_pid, zombie.exit_code)));
         }
 
-        self.current_task()
+        if self
+            .tasks
+            .values()
+            .any(|child| is_target(child.pid, child.ppid))
+        {
+            Ok(None)
+        } else {
+            Err(())
+        }
     }
-    pub fn kill(&mut self, pid: usize) {
-        self.tasks.remove(&pid);
-        self.vruntime.retain(|(_, this_pid)| *this_pid != pid);
-        self.schedule();
+    /**
+     * Move the current task behind the other ready tasks of its run queue except the idle task,
+     * so they run first. A real-time task only goes behind the tasks of the same priority.
+     */
+    pub fn yield_current(&mut self) {
+        let cpu = cpu_id();
+        let (pid, idle_pid) = (self.current_pid(), self.idle_pids[cpu]);
+        let run_queue = &mut self.run_queues[cpu];
+        let task = self.tasks.get_mut(&pid).unwrap();
+        if task.is_rt() {
+            if run_queue.remove_rt(pid).is_some() {
+                task.rr_runtime = 0;
+                run_queue.insert_rt(task.rt_priority, pid);
+            }
+            return;
+        }
+        let Some(last) = run_queue
+            .iter_rev()
+            .find(|(_, other)| *other != pid && *other != idle_pid)
+            .map(|(vruntime, _)| *vruntime)
+        else {
+            return;
+        };
+        if let Some(vruntime) = run_queue.remove(pid) {
+            task.vruntime = core::cmp::max(vruntime, last + 1);
+            run_queue.insert(task.vruntime, pid);
+        }
     }
     /**
      * Schedule, store context of current task, and set the context for the next task,
-     * and return the next task.
+     * and return the next task. The current task may have exited, in which case its context is
+     * discarded.
      */
     pub fn switch_task(&mut self, ctx: *mut Context) -> &Task {
-        self.current_task_mut().context = unsafe { ctx.read() };
+        let current_pid = self.current_pid();
+        if let Some(task) = self.tasks.get_mut(&current_pid) {
+            task.context = unsafe { ctx.read() };
+        }
         let next_task = self.schedule();
         let next_ctx = next_task.context.clone();
         unsafe { ctx.write(next_ctx) };
 
         next_task
     }
-    /** Fork current task. */
+    /**
+     * Fork current task, open file descriptions are shared with the child.
+     *
+     * Memory is shared copy-on-write, writable allocations are mapped read-only in both tasks
+     * until either of them writes to it.
+     */
     pub fn fork(&mut self) -> usize {
         self.max_pid += 1;
         let pid = self.max_pid;
@@ -172,94 +848,306 @@ impl Scheduler {
         let mut page = PageMapper::new();
 
         page.map_kernel_region();
-        page.map_data(self.trap_stack.0, PhysPage::from(self.trap_stack).0, 16);
+        map_trap_stacks(&mut page);
 
-        let mut page_allocs = Vec::new();
-
-        for alloc in &self.current_task().page_allocs {
-            let (v_page, p_page, v_pages, flags) = alloc.as_ref();
-
-            if flags.contains(&PFlags::Exec) {
-                page.map_text_u(*v_page, p_page.0, *v_pages);
-                page_allocs.push(Arc::clone(alloc));
-            } else if flags.contains(&PFlags::Write) {
-                let p_page = PhysPage::from(VirtualPage(alloc_pages(ceil_to_power_2(*v_pages))));
-                page_allocs.push(Arc::new((*v_page, p_page, *v_pages, flags.clone())));
-                unsafe {
-                    page.map_data_u(*v_page, p_page.0, *v_pages);
-                    core::ptr::copy(
-                        (*v_page << PAGE_BITS) as *const u8,
-                        (VirtPage::from(p_page).0 << PAGE_BITS) as *mut u8,
-                        *v_pages << PAGE_BITS,
-                    );
-                }
-            } else {
-                page.map_rodata_u(*v_page, p_page.0, *v_pages);
-                page_allocs.push(Arc::clone(alloc));
-            }
+        let page_allocs = self.current_task().page_allocs.clone();
+        for alloc in &page_allocs {
+            map_alloc(&mut page, alloc);
+        }
+        /* write protect the pages of the parent */
+        let parent = self.current_task_mut();
+        for alloc in &parent.page_allocs {
+            map_alloc(&mut parent.page, alloc);
         }
         let child = Task {
-            uid: self.current_task().uid,
+            cred: self.current_task().cred.clone(),
             pid,
             ppid: self.current_task().pid,
             page,
             nice: self.current_task().nice,
+            exec_start: 0,
+            vruntime: 0,
+            policy: self.current_task().policy,
+            rt_priority: self.current_task().rt_priority,
+            rr_runtime: 0,
             context: self.current_task().context.clone(),
+            personality: self.current_task().personality,
             page_allocs,
-            next_schedule: None,
-            fds: FdTable::default(),
+            areas: self.current_task().areas.clone(),
+            brk_start: self.current_task().brk_start,
+            brk: self.current_task().brk,
+            sleep_deadline: None,
+            kernel_stack: None,
+            cpu: None,
+            rq_cpu: 0,
+            affinity: self.current_task().affinity,
+            fds: self.current_task().fds.clone(),
+            signals: self.current_task().signals.fork(),
+            state: TaskState::Ready,
         };
         self.tasks.insert(pid, child);
-        let (min_vruntime, _) = self.vruntime.first().unwrap();
-        self.vruntime.insert((*min_vruntime, pid));
+        self.enqueue_new(pid);
 
         pid
     }
 }
 
-#[derive(Default)]
+#[derive(Clone)]
+struct FdEntry {
+    file: OpenFile,
+    /** Close the descriptor on `exec`. */
+    cloexec: bool,
+}
+
+#[derive(Default, Clone)]
 pub struct FdTable {
-    max_fd: usize,
-    fds: BTreeMap<usize, VfsFile>,
+    fds: BTreeMap<usize, FdEntry>,
 }
 
 impl FdTable {
+    /** Find the lowest unused descriptor not less than `min_fd`. */
+    fn lowest_free(&self, min_fd: usize) -> usize {
+        let mut fd = min_fd;
+        for used_fd in self.fds.range(min_fd..).map(|(fd, _)| *fd) {
+            if used_fd != fd {
+                break;
+            }
+            fd += 1;
+        }
+        fd
+    }
+    fn insert(&mut self, fd: usize, file: OpenFile, cloexec: bool) {
+        self.fds.insert(fd, FdEntry { file, cloexec });
+    }
     pub fn add(&mut self, file: VfsFile) -> usize {
-        let fd = self.max_fd;
-        self.fds.insert(fd, file);
-        self.max_fd += 1;
+        let fd = self.lowest_free(0);
+        self.insert(fd, Arc::new(Spinlock::new(file)), false);
         fd
     }
-    pub fn get(&self, fd: usize) -> Option<&VfsFile> {
-        self.fds.get(&fd)
+    pub fn get(&self, fd: usize) -> Option<&OpenFile> {
+        self.fds.get(&fd).map(|entry| &entry.file)
+    }
+    /**
+     * Duplicate `fd` to the lowest unused descriptor not less than `min_fd`, and returns the new
+     * descriptor.
+     */
+    pub fn dup(&mut self, fd: usize, min_fd: usize, cloexec: bool) -> Option<usize> {
+        let file = Arc::clone(self.get(fd)?);
+        let new_fd = self.lowest_free(min_fd);
+        self.insert(new_fd, file, cloexec);
+        Some(new_fd)
+    }
+    /**
+     * Duplicate `old_fd` to `new_fd`, the description previously referred by `new_fd` is
+     * released.
+     */
+    pub fn dup_to(
+        &mut self,
+        vfs: &mut VirtualFileSystem,
+        old_fd: usize,
+        new_fd: usize,
+        cloexec: bool,
+    ) -> Option<usize> {
+        let file = Arc::clone(self.get(old_fd)?);
+        if let Some(replaced) = self.remove(new_fd) {
+            let _ = vfs.release(replaced);
+        }
+        self.insert(new_fd, file, cloexec);
+        Some(new_fd)
+    }
+    pub fn get_cloexec(&self, fd: usize) -> Option<bool> {
+        self.fds.get(&fd).map(|entry| entry.cloexec)
     }
-    pub fn get_mut(&mut self, fd: usize) -> Option<&mut VfsFile> {
-        self.fds.get_mut(&fd)
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) -> Option<()> {
+        self.fds.get_mut(&fd).map(|entry| entry.cloexec = cloexec)
     }
-    pub fn remove(&mut self, fd: usize) {
-        self.fds.remove(&fd);
+    /**
+     * Remove a descriptor, the open file description should be released with
+     * [VirtualFileSystem::release].
+     */
+    pub fn remove(&mut self, fd: usize) -> Option<OpenFile> {
+        self.fds.remove(&fd).map(|entry| entry.file)
+    }
+    /** Remove and release all descriptors. */
+    pub fn close_all(&mut self, vfs: &mut VirtualFileSystem) {
+        for (_, entry) in core::mem::take(&mut self.fds) {
+            let _ = vfs.release(entry.file);
+        }
+    }
+    /** Remove and release descriptors with close-on-exec flag. */
+    pub fn close_on_exec(&mut self, vfs: &mut VirtualFileSystem) {
+        let cloexec_fds = self
+            .fds
+            .iter()
+            .filter(|(_, entry)| entry.cloexec)
+            .map(|(fd, _)| *fd)
+            .collect::<Vec<usize>>();
+        for fd in cloexec_fds {
+            let _ = vfs.release(self.remove(fd).unwrap());
+        }
     }
 }
 
 pub const KERNEL_PID: usize = 0;
+/** The task adopting orphans, which is the first task created by the kernel. */
+pub const INIT_PID: usize = 1;
+/** First PID of the idle tasks of secondary CPUs, which are numbered by CPU from it */
+const IDLE_PID_BASE: usize = usize::MAX - MAX_CPUS;
 const NICE_DEFAULT: isize = 0;
-const NICE_MAX: isize = 19;
-const NICE_MIN: isize = -20;
+pub const NICE_MAX: isize = 19;
+pub const NICE_MIN: isize = -20;
+/** Weight of nice `0`, virtual runtime advances at real time with this weight */
+const NICE_0_WEIGHT: u64 = 1024;
+/** Weight of idle tasks, which only run for a tiny share while other tasks are ready */
+const IDLE_WEIGHT: u64 = 3;
+/** Weights from nice `-20` to `19`, each nice level is a change of about 10% of CPU time */
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/** Static priorities of real-time tasks, higher priorities run first */
+pub const RT_PRIORITY_MIN: usize = 1;
+pub const RT_PRIORITY_MAX: usize = 99;
+/**
+ * Virtual runtime in nanoseconds a woken up task may be behind the minimum of its run queue, so
+ * tasks which sleep are favored a little without being able to save up runtime
+ */
+const WAKEUP_SLACK: usize = 3_000_000;
+/** Time slice of round-robin tasks in nanoseconds */
+const RR_TIMESLICE: u64 = 100_000_000;
 
 type PageAllocInfo = Arc<(usize, PhysPage, usize, Vec<PFlags>)>; // (v_page, p_page, v_pages, flags)
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskState {
+    /** Runnable, the task is in the run queue. */
+    Ready,
+    /** Blocked until a child exits. */
+    WaitChild,
+    /** Blocked until the wait channel is woken up by [wake_channel]. */
+    Blocked(usize),
+    /** Stopped by a signal until `SIGCONT` or `SIGKILL` is sent. */
+    Stopped,
+    /** Sleeping until `sleep_deadline`, or until a signal is sent. */
+    Sleeping,
+    /** Parked in a [WaitQueue](crate::sync::WaitQueue) until woken up by [wake_task]. */
+    Parked,
+}
+
+/** Scheduling policy of a task, the values are the same as Linux. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchedPolicy {
+    /** `SCHED_OTHER`, fair share weighted by the nice value. */
+    Normal = 0,
+    /** `SCHED_FIFO`, runs until it blocks, yields or a higher priority task is ready. */
+    Fifo = 1,
+    /** `SCHED_RR`, `SCHED_FIFO` with a time slice among tasks of the same priority. */
+    RoundRobin = 2,
+}
+
+impl SchedPolicy {
+    pub fn from_raw(policy: u64) -> Option<Self> {
+        Some(match policy {
+            0 => Self::Normal,
+            1 => Self::Fifo,
+            2 => Self::RoundRobin,
+            _ => return None,
+        })
+    }
+}
+
+/** A virtual memory area whose pages are allocated on first access. */
+#[derive(Clone)]
+struct VmArea {
+    v_page: usize,
+    v_pages: usize,
+    flags: Vec<PFlags>,
+    /** Initial content of the area, zero-filled if `None` */
+    segment: Option<ElfSegment>,
+}
+
+impl VmArea {
+    fn contains(&self, v_page: usize) -> bool {
+        (self.v_page..self.v_page + self.v_pages).contains(&v_page)
+    }
+}
+
+/** File content of a `PT_LOAD` segment. */
+#[derive(Clone)]
+struct ElfSegment {
+    image: Arc<[u8]>,
+    v_addr: usize,
+    offset: usize,
+    file_size: usize,
+}
+
+/** Address space and initial state of a program loaded from an ELF image. */
+struct ProgramImage {
+    page: PageMapper,
+    areas: Vec<VmArea>,
+    context: Context,
+    /** Initial program break */
+    brk: usize,
+    personality: Personality,
+    stack: InitialStack,
+}
+
+/** Exit status of a terminated task kept for its parent. */
+pub struct Zombie {
+    pub ppid: usize,
+    pub exit_code: isize,
+}
+
 pub struct Task {
-    pub uid: usize,
+    pub cred: Credentials,
     pub pid: usize,
     pub ppid: usize,
     pub page: PageMapper,
     pub nice: isize,
+    /** Timestamp when the task was last picked to run, its runtime is charged from here */
+    exec_start: u64,
+    /**
+     * Virtual runtime of a fair task in the run queue of `rq_cpu`, kept while it is not ready so
+     * it resumes where it stopped
+     */
+    vruntime: usize,
+    pub policy: SchedPolicy,
+    /** Static priority of real-time policies, from `1` to `99`, `0` for fair tasks */
+    pub rt_priority: usize,
+    /** Runtime in the current time slice of a round-robin task */
+    rr_runtime: u64,
     pub context: Context,
+    /** Syscall ABI of the program */
+    pub personality: Personality,
     /** Track pages allocations */
     page_allocs: Vec<PageAllocInfo>,
-    /** Minimum timestamp for next schedule, set by `sleep` syscall */
-    pub next_schedule: Option<u64>,
+    /** Virtual areas loaded on demand, pages already loaded are in `page_allocs` */
+    areas: Vec<VmArea>,
+    /** Start of the heap, right after the highest loaded segment */
+    brk_start: usize,
+    /** Program break, the end of the heap */
+    brk: usize,
+    /**
+     * Timestamp when a sleep ends, kept after the task is woken up until the sleep syscall is
+     * restarted to find out if the sleep was interrupted.
+     */
+    pub sleep_deadline: Option<u64>,
+    /**
+     * Stack of a kernel thread or the idle task of a secondary CPU, the idle task of the boot
+     * CPU runs on the boot stack
+     */
+    kernel_stack: Option<VirtPage>,
+    /** CPU the task is running on */
+    pub cpu: Option<usize>,
+    /** CPU whose run queue the task is on, or is put on when woken up */
+    rq_cpu: usize,
+    /** Mask of CPUs the task is allowed to run on */
+    pub affinity: u64,
     pub fds: FdTable,
+    pub signals: SignalState,
+    pub state: TaskState,
 }
 
 unsafe impl Sync for Task {}
@@ -270,157 +1158,726 @@ impl Task {
             self.nice = nice;
         }
     }
+    /** Check if the task has a real-time policy. */
+    pub fn is_rt(&self) -> bool {
+        self.policy != SchedPolicy::Normal
+    }
+    /** Scheduling weight of the nice value. */
+    fn weight(&self) -> u64 {
+        NICE_TO_WEIGHT[(self.nice - NICE_MIN) as usize]
+    }
+    /** Check if the task is the idle task or a kernel thread, which run in kernel mode. */
     pub fn is_kernel(&self) -> bool {
-        self.pid == KERNEL_PID
+        self.pid == KERNEL_PID || self.kernel_stack.is_some()
+    }
+    /**
+     * Destroy the page table and free pages no longer shared with other tasks.
+     */
+    fn release_memory(&mut self) {
+        /* kernel tasks share the kernel's page table */
+        if !self.is_kernel() {
+            self.page.destroy();
+        }
+        for alloc in core::mem::take(&mut self.page_allocs) {
+            release_alloc(alloc);
+        }
+    }
+    /** Find `pages` unused virtual pages for a memory mapping. */
+    fn find_free_area(&self, pages: usize) -> Option<usize> {
+        let mut areas = self
+            .page_allocs
+            .iter()
+            .map(|alloc| (alloc.0, alloc.0 + alloc.2))
+            .chain(
+                self.areas
+                    .iter()
+                    .map(|area| (area.v_page, area.v_page + area.v_pages)),
+            )
+            .collect::<Vec<(usize, usize)>>();
+        areas.sort();
+
+        let mut start = MMAP_BASE;
+        for (area_start, area_end) in areas {
+            if area_end <= start {
+                continue;
+            }
+            if area_start >= start + pages {
+                break;
+            }
+            start = area_end;
+        }
+        if start + pages <= MMAP_END {
+            Some(start)
+        } else {
+            None
+        }
+    }
+    /**
+     * Check if `[v_page, v_page + pages)` only cuts through allocations at their boundaries,
+     * and returns the number of allocated pages in the range.
+     */
+    fn count_allocated(&self, v_page: usize, pages: usize) -> Option<usize> {
+        let mut count = 0;
+        for alloc in &self.page_allocs {
+            let (alloc_start, alloc_end) = (alloc.0, alloc.0 + alloc.2);
+            if alloc_end <= v_page || alloc_start >= v_page + pages {
+                continue;
+            }
+            if alloc_start < v_page || alloc_end > v_page + pages {
+                return None; // partially covered
+            }
+            count += alloc.2;
+        }
+        Some(count)
+    }
+    /** Check if no page in the range is allocated or belongs to a virtual area. */
+    fn is_free(&self, v_page: usize, pages: usize) -> bool {
+        self.count_allocated(v_page, pages) == Some(0)
+            && !self
+                .areas
+                .iter()
+                .any(|area| area.v_page < v_page + pages && v_page < area.v_page + area.v_pages)
+    }
+    /** Split the virtual area containing `v_page`, so that an area starts at `v_page`. */
+    fn split_area(&mut self, v_page: usize) {
+        if let Some(index) = self.areas.iter().position(|area| area.contains(v_page))
+            && self.areas[index].v_page != v_page
+        {
+            let area = &mut self.areas[index];
+            let mut tail = area.clone();
+            tail.v_page = v_page;
+            tail.v_pages = area.v_page + area.v_pages - v_page;
+            area.v_pages = v_page - area.v_page;
+            self.areas.push(tail);
+        }
+    }
+    /**
+     * Map zeroed anonymous pages, at `fixed` if specified which replaces existing mappings,
+     * and returns the first virtual page. Fails if more than [MMAP_MAX_PAGES] are requested.
+     */
+    pub fn mmap(&mut self, fixed: Option<usize>, pages: usize, flags: &[PFlags]) -> Option<usize> {
+        if pages > MMAP_MAX_PAGES {
+            return None;
+        }
+        let v_page = match fixed {
+            Some(v_page) => {
+                self.munmap(v_page, pages)?;
+                v_page
+            }
+            None => self.find_free_area(pages)?,
+        };
+
+        /* allocate each page individually to allow partial unmapping */
+        for v_page in v_page..v_page + pages {
+            let page = VirtualPage(alloc_pages(1));
+            unsafe { core::ptr::write_bytes((page.0 << PAGE_BITS) as *mut u8, 0, PAGE_SIZE) };
+            let p_page = PhysPage::from(page);
+            map_user_pages(&mut self.page, v_page, p_page, 1, flags);
+            self.page_allocs
+                .push(Arc::new((v_page, p_page, 1, flags.to_vec())));
+        }
+        Some(v_page)
+    }
+    /**
+     * Unmap allocations and virtual areas in the range, allocations cannot be partially
+     * unmapped.
+     */
+    pub fn munmap(&mut self, v_page: usize, pages: usize) -> Option<()> {
+        self.count_allocated(v_page, pages)?;
+
+        self.split_area(v_page);
+        self.split_area(v_page + pages);
+        self.areas
+            .retain(|area| area.v_page < v_page || area.v_page >= v_page + pages);
+
+        let (unmapped, kept) = core::mem::take(&mut self.page_allocs)
+            .into_iter()
+            .partition(|alloc| v_page <= alloc.0 && alloc.0 + alloc.2 <= v_page + pages);
+        self.page_allocs = kept;
+        for alloc in unmapped {
+            self.page.unmap(alloc.0, alloc.2);
+            release_alloc(alloc);
+        }
+        Some(())
+    }
+    /** Find the allocation containing the virtual page. */
+    fn find_alloc(&self, v_page: usize) -> Option<usize> {
+        self.page_allocs
+            .iter()
+            .position(|alloc| (alloc.0..alloc.0 + alloc.2).contains(&v_page))
+    }
+    /**
+     * Copy an allocation shared with other tasks, and map it with its own access flags.
+     *
+     * Copy-on-write works at allocation granularity, the whole allocation is copied.
+     */
+    fn unshare_alloc(&mut self, index: usize) {
+        let alloc = &mut self.page_allocs[index];
+        if Arc::strong_count(alloc) > 1 {
+            let (v_page, p_page, v_pages, flags) = alloc.as_ref();
+            let new_p_page = PhysPage::from(VirtualPage(alloc_pages(ceil_to_power_2(*v_pages))));
+            unsafe {
+                core::ptr::copy(
+                    (VirtPage::from(*p_page).0 << PAGE_BITS) as *const u8,
+                    (VirtPage::from(new_p_page).0 << PAGE_BITS) as *mut u8,
+                    *v_pages << PAGE_BITS,
+                );
+            }
+            let old_alloc = core::mem::replace(
+                alloc,
+                Arc::new((*v_page, new_p_page, *v_pages, flags.clone())),
+            );
+            release_alloc(old_alloc); // may be the last owner if other tasks exited meanwhile
+        }
+        map_alloc(&mut self.page, &self.page_allocs[index]);
     }
     /**
-     * Returns the length of copied bytes.
+     * Find the allocation containing the virtual page, the page is loaded first if it belongs
+     * to a virtual area.
      *
      * Requirements:
      * * Switched to kernel's page table.
      */
-    pub fn copy_from_user(&self, mut user_addr: usize, mut kernel_buf: &mut [u8]) -> usize {
-        let buf_size = kernel_buf.len();
-        'main: while !kernel_buf.is_empty() {
-            for alloc in &self.page_allocs {
-                let (vpage, p_page, page_count, _flags) = alloc.as_ref();
-                if (*vpage..vpage + page_count).contains(&(user_addr >> PAGE_BITS)) {
-                    let mut offset = user_addr - (vpage << PAGE_BITS);
-                    while !kernel_buf.is_empty() && offset < page_count << PAGE_BITS {
-                        kernel_buf[0] = unsafe {
-                            ((VirtPage::from(*p_page).0 * PAGE_SIZE + offset) as *const u8).read()
-                        };
-                        kernel_buf = &mut kernel_buf[1..];
-                        offset += 1;
-                    }
-                    if kernel_buf.is_empty() {
-                        break 'main;
-                    } else {
-                        user_addr = (vpage + page_count) << PAGE_BITS;
-                        continue 'main;
-                    }
+    fn load_page(&mut self, v_page: usize) -> Option<usize> {
+        if let Some(index) = self.find_alloc(v_page) {
+            return Some(index);
+        }
+        let area = self.areas.iter().find(|area| area.contains(v_page))?;
+
+        let page = VirtualPage(alloc_pages(1));
+        let page_addr = page.0 << PAGE_BITS;
+        unsafe { core::ptr::write_bytes(page_addr as *mut u8, 0, PAGE_SIZE) };
+        if let Some(segment) = &area.segment {
+            /* part of the segment file content in this page */
+            let start = core::cmp::max(v_page << PAGE_BITS, segment.v_addr);
+            let end = core::cmp::min(
+                (v_page + 1) << PAGE_BITS,
+                segment.v_addr + segment.file_size,
+            );
+            if start < end {
+                let offset = segment.offset + start - segment.v_addr;
+                unsafe {
+                    core::ptr::copy(
+                        segment.image[offset..offset + end - start].as_ptr(),
+                        (page_addr + start - (v_page << PAGE_BITS)) as *mut u8,
+                        end - start,
+                    );
                 }
             }
-            break; // kernel_buf is not full but cannot dump from user space anymore
         }
-        buf_size - kernel_buf.len()
+
+        let alloc = Arc::new((v_page, PhysPage::from(page), 1, area.flags.clone()));
+        map_alloc(&mut self.page, &alloc);
+        self.page_allocs.push(alloc);
+        Some(self.page_allocs.len() - 1)
     }
     /**
-     * Returns the length of copied bytes.
+     * Resolve a page fault at `addr` by loading the page on demand, or by copying a
+     * copy-on-write page on write. Returns `false` if the access is not allowed.
      *
      * Requirements:
      * * Switched to kernel's page table.
      */
-    pub fn copy_to_user(&self, mut user_addr: usize, mut kernel_buf: &[u8]) -> usize {
-        let buf_size = kernel_buf.len();
-        'main: while !kernel_buf.is_empty() {
-            for alloc in &self.page_allocs {
-                let (vpage, p_page, page_count, _flags) = alloc.as_ref();
-                if (*vpage..vpage + page_count).contains(&(user_addr >> PAGE_BITS)) {
-                    let mut offset = user_addr - (vpage << PAGE_BITS);
-                    while !kernel_buf.is_empty() && offset < page_count << PAGE_BITS {
-                        unsafe {
-                            ((VirtPage::from(*p_page).0 * PAGE_SIZE + offset) as *mut u8)
-                                .write(kernel_buf[0]);
-                        };
-                        kernel_buf = &kernel_buf[1..];
-                        offset += 1;
-                    }
-                    if kernel_buf.is_empty() {
-                        break 'main;
-                    } else {
-                        user_addr = (vpage + page_count) << PAGE_BITS;
-                        continue 'main;
+    pub fn handle_page_fault(&mut self, addr: usize, write: bool) -> bool {
+        let v_page = addr >> PAGE_BITS;
+        let loaded = self.find_alloc(v_page).is_none();
+        let Some(index) = self.load_page(v_page) else {
+            return false;
+        };
+
+        if write {
+            if !self.page_allocs[index].3.contains(&PFlags::Write) {
+                return false;
+            }
+            self.unshare_alloc(index);
+            true
+        } else {
+            loaded // faults on a present page without write are access violations
+        }
+    }
+    /**
+     * Move the program break to `addr`, and returns the new program break, or the current one
+     * if it cannot be moved.
+     */
+    pub fn brk(&mut self, addr: usize) -> usize {
+        if addr < self.brk_start || addr > MMAP_BASE << PAGE_BITS {
+            return self.brk;
+        }
+        let old_end = self.brk.div_ceil(PAGE_SIZE);
+        let new_end = addr.div_ceil(PAGE_SIZE);
+
+        if new_end > old_end {
+            if !self.is_free(old_end, new_end - old_end) {
+                return self.brk; // collides with another mapping
+            }
+            /* allocate each page individually to allow shrinking page by page */
+            for v_page in old_end..new_end {
+                let page = VirtualPage(alloc_pages(1));
+                unsafe { core::ptr::write_bytes((page.0 << PAGE_BITS) as *mut u8, 0, PAGE_SIZE) };
+                let p_page = PhysPage::from(page);
+                self.page.map_data_u(v_page, p_page.0, 1);
+                self.page_allocs.push(Arc::new((
+                    v_page,
+                    p_page,
+                    1,
+                    alloc::vec![PFlags::Read, PFlags::Write],
+                )));
+            }
+        } else if new_end < old_end && self.munmap(new_end, old_end - new_end).is_none() {
+            return self.brk;
+        }
+        self.brk = addr;
+        self.brk
+    }
+    /**
+     * Change access flags of allocations and virtual areas in the range, which must be fully
+     * mapped.
+     */
+    pub fn mprotect(&mut self, v_page: usize, pages: usize, flags: &[PFlags]) -> Option<()> {
+        self.count_allocated(v_page, pages)?;
+        if !(v_page..v_page + pages).all(|page| {
+            self.find_alloc(page).is_some() || self.areas.iter().any(|area| area.contains(page))
+        }) {
+            return None;
+        }
+
+        self.split_area(v_page);
+        self.split_area(v_page + pages);
+        for area in &mut self.areas {
+            if (v_page..v_page + pages).contains(&area.v_page) {
+                area.flags = flags.to_vec();
+            }
+        }
+
+        for alloc in &mut self.page_allocs {
+            if !(v_page..v_page + pages).contains(&alloc.0) {
+                continue;
+            }
+            let (alloc_v_page, p_page, alloc_pages_count) = (alloc.0, alloc.1, alloc.2);
+            match Arc::get_mut(alloc) {
+                Some((_, _, _, alloc_flags)) => *alloc_flags = flags.to_vec(),
+                /* shared with other tasks, make a private copy */
+                None => {
+                    let new_p_page = PhysPage::from(VirtualPage(alloc_pages(ceil_to_power_2(
+                        alloc_pages_count,
+                    ))));
+                    unsafe {
+                        core::ptr::copy(
+                            (VirtPage::from(p_page).0 << PAGE_BITS) as *const u8,
+                            (VirtPage::from(new_p_page).0 << PAGE_BITS) as *mut u8,
+                            alloc_pages_count << PAGE_BITS,
+                        );
                     }
+                    *alloc =
+                        Arc::new((alloc_v_page, new_p_page, alloc_pages_count, flags.to_vec()));
                 }
             }
-            break; // kernel_buf is not full but cannot dump from user space anymore
+
+            self.page.unmap(alloc_v_page, alloc_pages_count);
+            map_user_pages(
+                &mut self.page,
+                alloc_v_page,
+                alloc.1,
+                alloc_pages_count,
+                flags,
+            );
         }
-        buf_size - kernel_buf.len()
+        Some(())
     }
-    pub fn copy_user_string(&self, user_addr: usize) -> String {
-        const BUF_SIZE: usize = 16;
-        let mut string_vec = Vec::new();
-        'main: loop {
-            let mut buf = [0; BUF_SIZE];
-            let len = self.copy_from_user(user_addr, &mut buf);
-            for byte in &buf[..len] {
-                if *byte == b'\0' {
-                    break 'main;
-                }
-                string_vec.push(*byte);
+    /**
+     * Translate a user page for kernel access. Pages of virtual areas are loaded on demand, and
+     * copy-on-write pages are unshared before writing.
+     *
+     * Fails with `EFAULT` if the page is not user accessible, or not writable for writes.
+     *
+     * Requirements:
+     * * Switched to kernel's page table.
+     */
+    fn user_page(&mut self, v_page: usize, write: bool) -> Result<VirtPage, Errno> {
+        if v_page >= USER_STACK_TOP {
+            return Err(Errno::EFAULT);
+        }
+        if let Some(entry) = self.page.translate(v_page)
+            && user_accessible(&entry, write)
+        {
+            return Ok(VirtPage::from(entry.page_number()));
+        }
+
+        /* not loaded yet, or mapped read-only for copy-on-write */
+        let index = self.load_page(v_page).ok_or(Errno::EFAULT)?;
+        if write && self.page_allocs[index].3.contains(&PFlags::Write) {
+            self.unshare_alloc(index);
+        }
+        match self.page.translate(v_page) {
+            Some(entry) if user_accessible(&entry, write) => {
+                Ok(VirtPage::from(entry.page_number()))
             }
-            if len < BUF_SIZE {
-                break;
+            _ => Err(Errno::EFAULT),
+        }
+    }
+    /**
+     * Copy `kernel_buf.len()` bytes from user space, page by page. Fails with `EFAULT` if any
+     * byte is not readable, the buffer may be partially filled.
+     *
+     * Requirements:
+     * * Switched to kernel's page table.
+     */
+    pub fn copy_from_user(
+        &mut self,
+        mut user_addr: usize,
+        mut kernel_buf: &mut [u8],
+    ) -> Result<(), Errno> {
+        while !kernel_buf.is_empty() {
+            let page = self.user_page(user_addr >> PAGE_BITS, false)?;
+            let offset = user_addr % PAGE_SIZE;
+            let size = core::cmp::min(kernel_buf.len(), PAGE_SIZE - offset);
+            unsafe {
+                core::ptr::copy(
+                    ((page.0 << PAGE_BITS) + offset) as *const u8,
+                    kernel_buf.as_mut_ptr(),
+                    size,
+                );
+            }
+            kernel_buf = &mut kernel_buf[size..];
+            user_addr += size;
+        }
+        Ok(())
+    }
+    /**
+     * Copy `kernel_buf` to user space, page by page. Fails with `EFAULT` if any byte is not
+     * writable, the user buffer may be partially written.
+     *
+     * Requirements:
+     * * Switched to kernel's page table.
+     */
+    pub fn copy_to_user(
+        &mut self,
+        mut user_addr: usize,
+        mut kernel_buf: &[u8],
+    ) -> Result<(), Errno> {
+        while !kernel_buf.is_empty() {
+            let page = self.user_page(user_addr >> PAGE_BITS, true)?;
+            let offset = user_addr % PAGE_SIZE;
+            let size = core::cmp::min(kernel_buf.len(), PAGE_SIZE - offset);
+            unsafe {
+                core::ptr::copy(
+                    kernel_buf.as_ptr(),
+                    ((page.0 << PAGE_BITS) + offset) as *mut u8,
+                    size,
+                );
             }
+            kernel_buf = &kernel_buf[size..];
+            user_addr += size;
         }
-        String::from_utf8_lossy(&string_vec).to_string()
+        Ok(())
+    }
+    /**
+     * Copy a null-terminated string from user space, `max_len` includes the null byte. Fails
+     * with `ENAMETOOLONG` if no null byte is found within `max_len` bytes.
+     *
+     * Requirements:
+     * * Switched to kernel's page table.
+     */
+    pub fn copy_user_string(
+        &mut self,
+        mut user_addr: usize,
+        max_len: usize,
+    ) -> Result<String, Errno> {
+        let mut string_vec = Vec::new();
+        while string_vec.len() < max_len {
+            let page = self.user_page(user_addr >> PAGE_BITS, false)?;
+            let offset = user_addr % PAGE_SIZE;
+            let size = core::cmp::min(max_len - string_vec.len(), PAGE_SIZE - offset);
+            let bytes = unsafe {
+                core::slice::from_raw_parts(((page.0 << PAGE_BITS) + offset) as *const u8, size)
+            };
+            if let Some(len) = bytes.iter().position(|byte| *byte == b'\0') {
+                string_vec.extend_from_slice(&bytes[..len]);
+                return Ok(String::from_utf8_lossy(&string_vec).to_string());
+            }
+            string_vec.extend_from_slice(bytes);
+            user_addr += size;
+        }
+        Err(Errno::ENAMETOOLONG)
     }
 }
 
 impl Drop for Task {
     fn drop(&mut self) {
-        self.page.destroy();
-        for alloc in &mut self.page_allocs {
-            let (_vpage, p_page, page_count, _flags) = alloc.as_ref();
-            if Arc::strong_count(alloc) == 1 {
-                free_pages(VirtPage::from(*p_page).0, ceil_to_power_2(*page_count));
+        self.release_memory();
+    }
+}
+
+/**
+ * Map user pages with access control converted from segment flags, pages without any access
+ * are left unmapped.
+ */
+fn map_user_pages(
+    page: &mut PageMapper,
+    v_page: usize,
+    p_page: PhysPage,
+    pages: usize,
+    flags: &[PFlags],
+) {
+    if flags.is_empty() {
+        return;
+    }
+
+    let mut acl = alloc::vec![PageACL::User, PageACL::Read];
+    if flags.contains(&PFlags::Write) {
+        acl.push(PageACL::Write);
+    }
+    if flags.contains(&PFlags::Exec) {
+        acl.push(PageACL::Execute);
+    }
+    page.map(v_page, p_page.0, pages, &acl);
+}
+
+/** Map an allocation, write protected while it is shared with other tasks. */
+fn map_alloc(page: &mut PageMapper, alloc: &PageAllocInfo) {
+    let (v_page, p_page, v_pages, flags) = alloc.as_ref();
+    if Arc::strong_count(alloc) > 1 {
+        let flags = flags
+            .iter()
+            .filter(|flag| **flag != PFlags::Write)
+            .copied()
+            .collect::<Vec<PFlags>>();
+        map_user_pages(page, *v_page, *p_page, *v_pages, &flags);
+    } else {
+        map_user_pages(page, *v_page, *p_page, *v_pages, flags);
+    }
+}
+
+/**
+ * Record the `PT_LOAD` segments of an image loaded at `base` as virtual areas, relative
+ * relocations are applied to a copy of the image if it is relocated. Returns the end of the
+ * highest segment.
+ */
+fn load_segments(
+    elf: &Elf,
+    elf_bytes: &[u8],
+    base: usize,
+    areas: &mut Vec<VmArea>,
+) -> Result<usize, Errno> {
+    let mut bytes = elf_bytes.to_vec();
+    if base != 0 {
+        reloc::relocate(elf, &mut bytes, base)?;
+    }
+
+    let image: Arc<[u8]> = Arc::from(bytes);
+    let mut end = 0;
+    for prog in &elf.p_headers {
+        if let PType::Load = prog.p_type {
+            let v_addr = base.checked_add(prog.v_addr).ok_or(Errno::ENOEXEC)?;
+            if prog.p_offset.saturating_add(prog.p_filesz) > elf_bytes.len()
+                || prog.p_filesz > prog.p_memsz
+                || v_addr.saturating_add(prog.p_memsz) > MMAP_END << PAGE_BITS
+            {
+                return Err(Errno::ENOEXEC);
             }
+
+            let v_page = v_addr >> PAGE_BITS;
+            let v_pages = (v_addr + prog.p_memsz).div_ceil(PAGE_SIZE) - v_page;
+            end = core::cmp::max(end, (v_page + v_pages) << PAGE_BITS);
+
+            areas.push(VmArea {
+                v_page,
+                v_pages,
+                flags: prog.p_flags.to_vec(),
+                segment: Some(ElfSegment {
+                    image: Arc::clone(&image),
+                    v_addr,
+                    offset: prog.p_offset,
+                    file_size: prog.p_filesz,
+                }),
+            });
         }
     }
+    Ok(end)
 }
 
-pub fn task_init() {
-    let trap_stack = VirtualPage(alloc_pages(16));
-    unsafe {
-        crate::trap::trap_stack_init(trap_stack);
+/** Path of the interpreter in `PT_INTERP`, a null-terminated string. */
+fn interp_path(elf: &Elf, elf_bytes: &[u8]) -> Result<Option<String>, Errno> {
+    let Some(interp) = elf
+        .p_headers
+        .iter()
+        .find(|prog| matches!(prog.p_type, PType::Interp))
+    else {
+        return Ok(None);
+    };
+    let path = elf_bytes
+        .get(interp.p_offset..interp.p_offset.saturating_add(interp.p_filesz))
+        .ok_or(Errno::ENOEXEC)?;
+    let path = path.split(|byte| *byte == b'\0').next().unwrap();
+    if path.is_empty() || path.len() >= PATH_MAX {
+        return Err(Errno::ENOEXEC);
+    }
+    Ok(Some(String::from_utf8_lossy(path).to_string()))
+}
+
+/** Check if a mapped page is readable, and writable for writes, from user space. */
+fn user_accessible<E: Entry>(entry: &E, write: bool) -> bool {
+    entry.has_acl(PageACL::User)
+        && entry.has_acl(PageACL::Read)
+        && (!write || entry.has_acl(PageACL::Write))
+}
+
+/** Free the pages of an allocation if it is not shared with other tasks. */
+fn release_alloc(alloc: PageAllocInfo) {
+    if let Some((_v_page, p_page, page_count, _flags)) = Arc::into_inner(alloc) {
+        free_pages(VirtPage::from(p_page).0, ceil_to_power_2(page_count));
+    }
+}
+
+/** Map the trap stacks of all CPUs into a user page table. */
+fn map_trap_stacks(page: &mut PageMapper) {
+    for trap_stack in TRAP_STACKS.lock().iter() {
+        page.map_data(
+            trap_stack.0,
+            PhysPage::from(*trap_stack).0,
+            TRAP_STACK_PAGES,
+        );
     }
+}
 
+/**
+ * Wake up tasks blocked on a wait channel, it takes effect on the next schedule so it can be
+ * called while holding any lock.
+ */
+pub fn wake_channel(channel: usize) {
+    WOKEN_CHANNELS.lock().push(channel);
+}
+
+/**
+ * Wake up a parked task, it takes effect on the next schedule so it can be called while holding
+ * any lock.
+ */
+pub fn wake_task(pid: usize) {
+    WOKEN_TASKS.lock().push(pid);
+}
+
+pub fn task_init() {
     let kernel_page = unsafe { PageMapper::from_root(VirtPage::from(KERNEL_PT.assume_init())) };
 
     let kernel_task = Task {
         page: kernel_page,
-        uid: 0,
+        cred: Credentials::default(),
         pid: KERNEL_PID,
         ppid: 0,
         nice: NICE_DEFAULT,
+        exec_start: 0,
+        vruntime: 0,
+        policy: SchedPolicy::Normal,
+        rt_priority: 0,
+        rr_runtime: 0,
         context: Context::default(),
+        personality: Personality::Native,
         page_allocs: Vec::default(),
-        next_schedule: None,
+        areas: Vec::default(),
+        brk_start: 0,
+        brk: 0,
+        sleep_deadline: None,
+        kernel_stack: None,
+        cpu: Some(0),
+        rq_cpu: 0,
+        affinity: cpu_bit(0),
         fds: FdTable::default(),
+        signals: SignalState::default(),
+        state: TaskState::Ready,
     };
 
     let mut tasks = BTreeMap::new();
     tasks.insert(kernel_task.pid, kernel_task);
-    let mut vruntime = BTreeSet::new();
-    vruntime.insert((0, KERNEL_PID));
+    let mut run_queue = RunQueue::default();
+    run_queue.insert(0, KERNEL_PID);
 
     *SCHEDULER.lock() = MaybeUninit::new(Scheduler {
         tasks,
-        vruntime,
-        current_pid: KERNEL_PID,
-        trap_stack,
+        run_queues: alloc::vec![run_queue],
+        zombies: BTreeMap::new(),
+        sleep_queue: BTreeSet::new(),
+        dead_kernel_stack: None,
+        current_pids: alloc::vec![KERNEL_PID],
+        idle_pids: alloc::vec![KERNEL_PID],
         max_pid: 0,
     });
 }
 
-pub unsafe fn kernel_fork() {
-    unsafe {
+/**
+ * Spawn a kernel thread running `func(arg)` in kernel mode on its own stack, with the kernel's
+ * page table. The thread exits when the function returns.
+ *
+ * Returns PID of the thread.
+ */
+pub fn spawn_kernel_thread(func: fn(usize), arg: usize) -> usize {
+    let stack = VirtualPage(alloc_pages(KERNEL_STACK_PAGES));
+    let stack_top = ((stack.0 + KERNEL_STACK_PAGES) << PAGE_BITS) as u64;
+
+    let mut context = Context::default();
+    #[cfg(target_arch = "riscv64")]
+    {
+        context.epc = kernel_thread_entry as *const () as u64;
+        context.x[2] = stack_top; // sp
+        context.x[9] = func as *const () as u64; // a0
+        context.x[10] = arg as u64; // a1
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        context.elr_el1 = kernel_thread_entry as *const () as u64;
+        context.sp = stack_top;
+        context.pstate = 0b0101; // EL1h, interrupts unmasked
+        context.x[0] = func as *const () as u64;
+        context.x[1] = arg as u64;
+    }
+
+    let mut scheduler_guard = SCHEDULER.lock();
+    let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+    scheduler.max_pid += 1;
+    let pid = scheduler.max_pid;
+    let task = Task {
+        cred: Credentials::default(),
+        pid,
+        ppid: KERNEL_PID,
+        page: unsafe { PageMapper::from_root(VirtPage::from(KERNEL_PT.assume_init())) },
+        nice: NICE_DEFAULT,
+        exec_start: 0,
+        vruntime: 0,
+        policy: SchedPolicy::Normal,
+        rt_priority: 0,
+        rr_runtime: 0,
+        context,
+        personality: Personality::Native,
+        page_allocs: Vec::default(),
+        areas: Vec::default(),
+        brk_start: 0,
+        brk: 0,
+        sleep_deadline: None,
+        kernel_stack: Some(stack),
+        cpu: None,
+        rq_cpu: 0,
+        affinity: CPU_MASK_ALL,
+        fds: FdTable::default(),
+        signals: SignalState::default(),
+        state: TaskState::Ready,
+    };
+    scheduler.tasks.insert(pid, task);
+    scheduler.enqueue_new(pid);
+
+    pid
+}
+
+/** Entry of kernel threads, which runs the thread function and exits the thread. */
+extern "C" fn kernel_thread_entry(func: usize, arg: usize) -> ! {
+    let func: fn(usize) = unsafe { core::mem::transmute(func) };
+    func(arg);
+
+    {
         let mut scheduler_guard = SCHEDULER.lock();
-        let scheduler = scheduler_guard.assume_init_mut();
-        let current_task = scheduler.current_task();
-        let new_task = Task {
-            uid: current_task.uid,
-            pid: scheduler.max_pid + 1,
-            page: PageMapper::new(),
-            ppid: current_task.pid,
-            nice: current_task.nice,
-            context: Context::default(),
-            page_allocs: Vec::default(),
-            next_schedule: None,
-            fds: FdTable::default(),
-        };
-        scheduler.tasks.insert(new_task.pid, new_task);
-        scheduler.max_pid += 1;
+        let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+        let pid = scheduler.current_task().pid;
+        scheduler.exit(pid, 0);
     }
+    /* never scheduled again */
+    crate::trap::yield_now();
+    unreachable!();
 }
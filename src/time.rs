@@ -1,3 +1,5 @@
+use crate::task::SCHEDULER;
+
 /**
  * Get current timestamp in nano-second.
  */
@@ -9,6 +11,15 @@ pub fn get_sys_time() -> u64 {
 }
 
 /**
- * Called by trap.
+ * Called by trap on every CPU, wakes up sleeping tasks and balances the load of the CPU.
+ *
+ * Requirements:
+ * * Switched to kernel's page table.
  */
-pub fn timer() {}
+pub fn timer() {
+    let mut scheduler_guard = SCHEDULER.lock();
+    let scheduler = unsafe { scheduler_guard.assume_init_mut() };
+    let now = get_sys_time();
+    scheduler.wake_sleepers(now);
+    scheduler.periodic_balance(now);
+}
@@ -1,5 +1,3 @@
-use crate::address::VirtPage;
-
 #[inline(always)]
 pub unsafe fn enable_interrupts() {
     #[cfg(target_arch = "riscv64")]
@@ -32,14 +30,41 @@ pub unsafe fn disable_interrupts() {
     };
 }
 
-pub unsafe fn trap_stack_init(trap_stack: VirtPage) {
+/**
+ * Trap into the kernel to switch to another task, used by kernel tasks to give up the CPU.
+ *
+ * Requirements:
+ * * Called by a kernel task outside trap handlers, with interrupts enabled.
+ */
+pub fn yield_now() {
     #[cfg(target_arch = "riscv64")]
+    crate::arch::riscv64::yield_now();
+    #[cfg(target_arch = "aarch64")]
+    crate::arch::arm64::yield_now();
+}
+
+/** Set the address of the per-CPU area of this CPU, which is also the top of its trap stack. */
+pub unsafe fn per_cpu_init(per_cpu: usize) {
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!("csrw sscratch, {}", in(reg) per_cpu);
+    }
+    #[cfg(target_arch = "aarch64")]
     unsafe {
-        use crate::page::PAGE_BITS;
-        use core::arch::asm;
+        core::arch::asm!("msr TPIDR_EL1, {}", in(reg) per_cpu);
+    }
+}
 
-        asm!("csrw sscratch, {}", in(reg) (trap_stack.0 + 16) << PAGE_BITS);
+/** Address of the per-CPU area of this CPU. */
+pub fn per_cpu_ptr() -> usize {
+    let per_cpu: usize;
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!("csrr {}, sscratch", out(reg) per_cpu);
     }
     #[cfg(target_arch = "aarch64")]
-    let _ = trap_stack;
+    unsafe {
+        core::arch::asm!("mrs {}, TPIDR_EL1", out(reg) per_cpu);
+    }
+    per_cpu
 }
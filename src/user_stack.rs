@@ -0,0 +1,128 @@
+/*!
+ * Initial user stack of a program in the System V layout, from the stack pointer upwards:
+ * `argc`, the null-terminated `argv` and `envp` pointer arrays, the auxiliary vector ended by
+ * `AT_NULL`, then the `AT_RANDOM` bytes and the argument and environment strings.
+ */
+
+use crate::{
+    page::PAGE_SIZE,
+    rand::{GLOBAL_RNG, RandomGenerator},
+};
+use alloc::{string::String, vec::Vec};
+
+/** Auxiliary vector entry types */
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_ENTRY: usize = 9;
+pub const AT_HWCAP: usize = 16;
+pub const AT_RANDOM: usize = 25;
+
+/** Base ISA extensions as bits of their letters: I, M, A and C. */
+#[cfg(target_arch = "riscv64")]
+const HWCAP: usize = isa_bit(b'I') | isa_bit(b'M') | isa_bit(b'A') | isa_bit(b'C');
+/** `HWCAP_FP` and `HWCAP_ASIMD` */
+#[cfg(target_arch = "aarch64")]
+const HWCAP: usize = 1 << 0 | 1 << 1;
+#[cfg(target_arch = "x86_64")]
+const HWCAP: usize = 0;
+
+#[cfg(target_arch = "riscv64")]
+const fn isa_bit(letter: u8) -> usize {
+    1 << (letter - b'A')
+}
+
+/** Size of the `AT_RANDOM` bytes */
+const RANDOM_SIZE: usize = 16;
+const STACK_ALIGN: usize = 16;
+const WORD_SIZE: usize = size_of::<usize>();
+
+/** Content of the initial stack, to be copied to `sp`. */
+pub struct InitialStack {
+    pub sp: usize,
+    /** Address of the `argv` array */
+    pub argv: usize,
+    /** Address of the `envp` array */
+    pub envp: usize,
+    pub bytes: Vec<u8>,
+}
+
+/** Bytes taken on the stack by the strings and the pointer arrays of `argv` and `envp`. */
+pub fn args_size(args: &[String]) -> usize {
+    args.iter()
+        .map(|arg| arg.len() + 1 + WORD_SIZE)
+        .sum::<usize>()
+        + WORD_SIZE
+}
+
+/**
+ * Build the initial stack below `stack_top`. `auxv` holds the entries of the program, `AT_PAGESZ`,
+ * `AT_HWCAP` and `AT_RANDOM` are appended.
+ */
+pub fn build(
+    stack_top: usize,
+    argv: &[String],
+    envp: &[String],
+    auxv: &[(usize, usize)],
+) -> InitialStack {
+    /* strings at the top, then the random bytes */
+    let strings_size = argv
+        .iter()
+        .chain(envp)
+        .map(|arg| arg.len() + 1)
+        .sum::<usize>();
+    let strings_addr = stack_top - strings_size;
+    let random_addr = (strings_addr - RANDOM_SIZE) & !(WORD_SIZE - 1);
+
+    let mut auxv = auxv.to_vec();
+    auxv.extend_from_slice(&[
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_HWCAP, HWCAP),
+        (AT_RANDOM, random_addr),
+        (AT_NULL, 0),
+    ]);
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + auxv.len() * 2;
+    let sp = (random_addr - words * WORD_SIZE) & !(STACK_ALIGN - 1);
+
+    let mut bytes = alloc::vec![0; stack_top - sp];
+    let mut put = |addr: usize, data: &[u8]| {
+        bytes[addr - sp..addr - sp + data.len()].copy_from_slice(data);
+    };
+
+    let mut vector = Vec::with_capacity(words);
+    vector.push(argv.len());
+    let mut string_addr = strings_addr;
+    for args in [argv, envp] {
+        for arg in args {
+            put(string_addr, arg.as_bytes());
+            vector.push(string_addr);
+            string_addr += arg.len() + 1; // the null byte is already zero
+        }
+        vector.push(0);
+    }
+    for (key, value) in auxv {
+        vector.extend_from_slice(&[key, value]);
+    }
+
+    let mut random = [0; RANDOM_SIZE];
+    let mut rng_guard = GLOBAL_RNG.lock();
+    let rng = unsafe { rng_guard.assume_init_mut() };
+    rng.gen_bytes(&mut random);
+    drop(rng_guard);
+    put(random_addr, &random);
+    let vector_bytes = vector
+        .iter()
+        .flat_map(|word| word.to_ne_bytes())
+        .collect::<Vec<u8>>();
+    put(sp, &vector_bytes);
+
+    InitialStack {
+        sp,
+        argv: sp + WORD_SIZE,
+        envp: sp + WORD_SIZE * (argv.len() + 2),
+        bytes,
+    }
+}
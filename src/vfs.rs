@@ -1,11 +1,15 @@
 /*! Virtual File System */
 
 use crate::{
+    cred::Credentials,
     devfs::DevFS,
     global::GlobalUninit,
     path::{Path, PathBuf},
+    pipefs::PipeFS,
+};
+use alloc::{
+    borrow::ToOwned, boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec,
 };
-use alloc::{borrow::ToOwned, boxed::Box, collections::BTreeMap, string::String, vec::Vec};
 use core::{mem::MaybeUninit, result::Result};
 use spinlock::Spinlock;
 
@@ -15,9 +19,9 @@ pub fn vfs_init() {
     unsafe {
         let mut rootfs = ROOT_VFS.lock();
         *rootfs = MaybeUninit::new(VirtualFileSystem::default());
-        rootfs
-            .assume_init_mut()
-            .mount(Box::<DevFS>::default(), "/dev");
+        let rootfs = rootfs.assume_init_mut();
+        rootfs.mount(Box::<DevFS>::default(), "/dev");
+        rootfs.pipe_fs_id = Some(rootfs.mount_anonymous(Box::<PipeFS>::default()));
     }
 }
 
@@ -32,27 +36,51 @@ pub enum FsError {
     NoSuchFile,
     NotSupported,
     PermissionDenied,
+    /** The descriptor is not open for reading or writing. */
+    BadAccessMode,
+    /** Operation would block, wait on the wait channel and try again. */
+    WouldBlock(usize),
+    /** Write to a pipe without readers. */
+    BrokenPipe,
     Other,
 }
 
+/** Access modes, same as the permission bits of others. */
+pub const ACCESS_READ: u16 = 0o4;
+pub const ACCESS_WRITE: u16 = 0o2;
+pub const ACCESS_EXEC: u16 = 0o1;
+
 #[derive(Debug)]
 pub struct VfsFile {
     pub fd: File,
     pub offset: u64,
     pub fs_id: usize,
+    /** Allowed access modes, [ACCESS_READ] and [ACCESS_WRITE] */
+    pub access: u16,
 }
 
+/**
+ * An open file description shared by file descriptors, such as descriptors duplicated by fork.
+ */
+pub type OpenFile = Arc<Spinlock<VfsFile>>;
+
 #[derive(Default)]
 pub struct VirtualFileSystem {
     max_id: usize,
     pub mount_points: BTreeMap<usize, PathBuf>,
     pub mounted_fs: BTreeMap<usize, Box<dyn FileSystem>>,
+    /** Filesystem of anonymous pipes. */
+    pipe_fs_id: Option<usize>,
 }
 
 unsafe impl Send for VirtualFileSystem {}
 
 impl VirtualFileSystem {
-    pub fn open<P>(&mut self, path: P) -> Result<VfsFile, VfsError>
+    /**
+     * Open a file, `access` is checked against the permissions of the file with the
+     * credentials.
+     */
+    pub fn open<P>(&mut self, path: P, cred: &Credentials, access: u16) -> Result<VfsFile, VfsError>
     where
         P: AsRef<Path>,
     {
@@ -74,10 +102,15 @@ impl VirtualFileSystem {
         if let Some(fs) = found_fs {
             let prefix = found_mount_point.unwrap();
             match fs.open(&path.to_owned().strip_prefix(prefix).unwrap()) {
+                Ok(fd) if !fd.perm.check(cred, access) => {
+                    let _ = fs.close(&fd);
+                    Err(VfsError::FsError(FsError::PermissionDenied))
+                }
                 Ok(fd) => Ok(VfsFile {
                     fd,
                     offset: 0,
                     fs_id: found_fs_id,
+                    access: access & (ACCESS_READ | ACCESS_WRITE),
                 }),
                 Err(err) => Err(VfsError::FsError(err)),
             }
@@ -97,6 +130,13 @@ impl VirtualFileSystem {
             .insert(self.max_id, mount_point.to_owned());
         self.max_id += 1;
     }
+    /** Mount a filesystem without mount point, returns the filesystem id. */
+    pub fn mount_anonymous(&mut self, fs: Box<dyn FileSystem>) -> usize {
+        let fs_id = self.max_id;
+        self.mounted_fs.insert(fs_id, fs);
+        self.max_id += 1;
+        fs_id
+    }
     pub fn umount<P>(&mut self, mount_point: P)
     where
         P: AsRef<Path>,
@@ -125,6 +165,9 @@ impl VirtualFileSystem {
         None
     }
     pub fn read(&mut self, fd: &mut VfsFile, buf: &mut [u8]) -> Result<u64, FsError> {
+        if fd.access & ACCESS_READ == 0 {
+            return Err(FsError::BadAccessMode);
+        }
         match self
             .mounted_fs
             .get_mut(&fd.fs_id)
@@ -139,6 +182,9 @@ impl VirtualFileSystem {
         }
     }
     pub fn write(&mut self, fd: &mut VfsFile, buf: &[u8]) -> Result<u64, FsError> {
+        if fd.access & ACCESS_WRITE == 0 {
+            return Err(FsError::BadAccessMode);
+        }
         match self
             .mounted_fs
             .get_mut(&fd.fs_id)
@@ -152,9 +198,64 @@ impl VirtualFileSystem {
             Err(err) => Err(err),
         }
     }
+    /** Create an anonymous pipe, returns the read end and the write end. */
+    pub fn pipe(&mut self) -> Result<(VfsFile, VfsFile), FsError> {
+        let fs_id = self.pipe_fs_id.ok_or(FsError::NotSupported)?;
+        let (read_end, write_end) = self.mounted_fs.get_mut(&fs_id).unwrap().pipe()?;
+        Ok((
+            VfsFile {
+                fd: read_end,
+                offset: 0,
+                fs_id,
+                access: ACCESS_READ,
+            },
+            VfsFile {
+                fd: write_end,
+                offset: 0,
+                fs_id,
+                access: ACCESS_WRITE,
+            },
+        ))
+    }
+    /** Read from the current offset until the end of file. */
+    pub fn read_all(&mut self, fd: &mut VfsFile) -> Result<Vec<u8>, FsError> {
+        const BUF_SIZE: usize = 4096;
+        let mut bytes = Vec::new();
+        let mut buf = [0; BUF_SIZE];
+        loop {
+            let size = self.read(fd, &mut buf)? as usize;
+            if size == 0 {
+                break;
+            }
+            bytes.extend_from_slice(&buf[..size]);
+        }
+        Ok(bytes)
+    }
+    /**
+     * Read a whole program, which requires the execute permission. The content is read by the
+     * kernel, the read permission is not required.
+     */
+    pub fn read_executable(&mut self, path: &str, cred: &Credentials) -> Result<Vec<u8>, VfsError> {
+        let mut fd = self.open(path, cred, ACCESS_EXEC)?;
+        fd.access = ACCESS_READ;
+        let bytes = self.read_all(&mut fd);
+        let _ = self.close(&fd);
+        bytes.map_err(VfsError::FsError)
+    }
     pub fn close(&mut self, fd: &VfsFile) -> Result<(), FsError> {
         self.mounted_fs.get_mut(&fd.fs_id).unwrap().close(&fd.fd)
     }
+    /**
+     * Drop a reference to an open file description, the file is closed along with the last
+     * reference.
+     */
+    pub fn release(&mut self, file: OpenFile) -> Result<(), FsError> {
+        if Arc::strong_count(&file) == 1 {
+            self.close(&file.lock())
+        } else {
+            Ok(())
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -164,12 +265,47 @@ pub enum FileType {
     CharDev,
     BlockDev,
     SymbolLink,
+    Pipe,
+}
+
+/** Owner and permission bits of a file. */
+#[derive(Clone, Copy, Debug)]
+pub struct Permissions {
+    pub uid: usize,
+    pub gid: usize,
+    /** Permission bits, such as `0o644` */
+    pub mode: u16,
+}
+
+impl Permissions {
+    pub const fn new(uid: usize, gid: usize, mode: u16) -> Self {
+        Self { uid, gid, mode }
+    }
+    /**
+     * Check if all access modes in `access` are granted. The owner bits apply to the owner,
+     * the group bits apply to members of the group, and the others bits apply to anyone else.
+     * Root is granted any access, except execution of a file without any execute bit.
+     */
+    pub fn check(&self, cred: &Credentials, access: u16) -> bool {
+        if cred.is_root() {
+            return access & ACCESS_EXEC == 0 || self.mode & 0o111 != 0;
+        }
+        let bits = if cred.euid == self.uid {
+            self.mode >> 6
+        } else if cred.in_group(self.gid) {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        bits & access == access
+    }
 }
 
 #[derive(Debug)]
 pub struct File {
     pub fd: u64,
     pub r#type: FileType,
+    pub perm: Permissions,
 }
 
 pub trait FileSystem {
@@ -189,4 +325,8 @@ pub trait FileSystem {
     ) -> Result<(), FsError> {
         Err(FsError::NotSupported)
     }
+    /** Create a pipe, returns the read end and the write end. */
+    fn pipe(&mut self) -> Result<(File, File), FsError> {
+        Err(FsError::NotSupported)
+    }
 }
@@ -0,0 +1,169 @@
+/*!
+ * Embed the function symbols of a linked kernel into its `.ksyms` section, which the kernel uses
+ * to symbolize backtraces. The section is reserved at link time and patched in place, so no
+ * address of the kernel is changed.
+ *
+ * The table is little-endian: the count of symbols as a `u64`, the symbols sorted by address as
+ * `(address: u64, size: u32, name offset: u32)`, then the null-terminated names.
+ */
+
+use elf::{Elf, SymType};
+use std::{env, fs, process};
+
+const KSYMS_SECTION: &str = ".ksyms";
+const ENTRY_SIZE: usize = 16;
+
+/**
+ * Demangle a legacy Rust symbol such as `_ZN5alloc6string6String4push17h0123456789abcdefE` into
+ * `alloc::string::String::push`. Other names are kept as they are.
+ */
+fn demangle(name: &str) -> String {
+    let Some(mut rest) = name
+        .strip_prefix("_ZN")
+        .and_then(|name| name.strip_suffix('E'))
+    else {
+        return name.to_string();
+    };
+
+    let mut parts = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let Ok(len) = rest[..digits].parse::<usize>() else {
+            return name.to_string();
+        };
+        let Some(part) = rest.get(digits..digits + len) else {
+            return name.to_string();
+        };
+        parts.push(part);
+        rest = &rest[digits + len..];
+    }
+    /* the last part is the hash */
+    if parts
+        .last()
+        .is_some_and(|hash| hash.len() == 17 && hash.starts_with('h'))
+    {
+        parts.pop();
+    }
+
+    parts
+        .iter()
+        .map(|part| {
+            /* parts starting with an escape are prefixed by `_` */
+            unescape(
+                part.strip_prefix('_')
+                    .filter(|part| part.starts_with('$'))
+                    .unwrap_or(part),
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("::")
+}
+
+/** Replace the `$..$` escapes and `..` of a legacy symbol part. */
+fn unescape(part: &str) -> String {
+    let mut result = String::new();
+    let mut rest = part;
+    while !rest.is_empty() {
+        if let Some(escaped) = rest.strip_prefix('$')
+            && let Some(end) = escaped.find('$')
+        {
+            let code = &escaped[..end];
+            let replaced = match code {
+                "SP" => Some('@'),
+                "BP" => Some('*'),
+                "RF" => Some('&'),
+                "LT" => Some('<'),
+                "GT" => Some('>'),
+                "LP" => Some('('),
+                "RP" => Some(')'),
+                "C" => Some(','),
+                _ => code
+                    .strip_prefix('u')
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32),
+            };
+            if let Some(c) = replaced {
+                result.push(c);
+                rest = &escaped[end + 1..];
+                continue;
+            }
+        }
+        if let Some(after) = rest.strip_prefix("..") {
+            result.push_str("::");
+            rest = after;
+        } else {
+            let c = rest.chars().next().unwrap();
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    result
+}
+
+/** Build the table of the function symbols of the kernel. */
+fn build_table(elf: &Elf, bytes: &[u8]) -> Vec<u8> {
+    let mut symbols = elf
+        .symtab(bytes)
+        .map(|symbols| {
+            symbols
+                .filter(|symbol| symbol.st_type == SymType::Func && symbol.st_shndx != 0)
+                .map(|symbol| (symbol.st_value, symbol.st_size, demangle(symbol.name)))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    symbols.sort_by_key(|(addr, ..)| *addr);
+    symbols.dedup_by_key(|(addr, ..)| *addr);
+
+    let mut entries = Vec::with_capacity(8 + symbols.len() * ENTRY_SIZE);
+    let mut names = Vec::new();
+    entries.extend_from_slice(&(symbols.len() as u64).to_le_bytes());
+    for (addr, size, name) in &symbols {
+        entries.extend_from_slice(&(*addr as u64).to_le_bytes());
+        entries.extend_from_slice(&(*size as u32).to_le_bytes());
+        entries.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+    entries.extend_from_slice(&names);
+    entries
+}
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: ksyms <kernel>");
+        process::exit(1);
+    };
+    let mut bytes = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("ksyms: cannot read {}: {}", path, err);
+        process::exit(1);
+    });
+    let elf = Elf::parse(&bytes).unwrap_or_else(|err| {
+        eprintln!("ksyms: invalid ELF {}: {:?}", path, err);
+        process::exit(1);
+    });
+    let Some(section) = elf
+        .section(KSYMS_SECTION)
+        .filter(|section| section.data(&bytes).is_some())
+    else {
+        eprintln!("ksyms: no {} section in {}", KSYMS_SECTION, path);
+        process::exit(1);
+    };
+
+    let table = build_table(&elf, &bytes);
+    if table.len() > section.sh_size {
+        eprintln!(
+            "ksyms: symbol table of {} bytes does not fit in {} bytes",
+            table.len(),
+            section.sh_size
+        );
+        process::exit(1);
+    }
+    let (offset, size) = (section.sh_offset, section.sh_size);
+    bytes[offset..offset + size].fill(0);
+    bytes[offset..offset + table.len()].copy_from_slice(&table);
+
+    if let Err(err) = fs::write(&path, &bytes) {
+        eprintln!("ksyms: cannot write {}: {}", path, err);
+        process::exit(1);
+    }
+}